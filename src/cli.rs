@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use redalt_steamup_installer::modules::{chain, creator};
+use redalt_steamup_installer::modules::output::{self, Event};
+use redalt_steamup_installer::{Changes, Error, Manifest};
+use crate::settings::Settings;
+
+/// Command-line options and the settings fields they map to.
+const OPTIONS: [(&str, &str); 14] = [
+    ("--changes", "changes_file"),
+    ("--game-dir", "game_directory"),
+    ("--update-dir", "update_directory"),
+    ("--update-archive", "update_archive"),
+    ("--manifest", "manifest_file"),
+    ("--initial-manifest", "initial_manifest_file"),
+    ("--check-initial-build", "check_initial_build"),
+    ("--validate-update", "validate_update"),
+    ("--validate-game", "validate_game"),
+    ("--validation-threads", "validation_threads"),
+    ("--create-backup", "create_backup"),
+    ("--backup-format", "backup_format"),
+    ("--copy-files", "copy_files"),
+    ("--remove-files", "remove_files"),
+];
+
+/// Command-line options that only apply to some commands.
+const COMMAND_OPTIONS: [(&str, &str); 7] = [
+    ("--name", "Name of the game (create)."),
+    ("--app", "App ID (create)."),
+    ("--depot", "Depot ID (create)."),
+    ("--initial-build", "Build of the old files (create)."),
+    ("--final-build", "Build of the new files (create)."),
+    ("--keep", "Number of the newest backups to keep (backup prune)."),
+    ("--older-than", "Only remove backups older than this many days (backup prune)."),
+];
+
+pub fn usage() -> String {
+    let mut usage = String::from("Usage: RedAlt-SteamUp-Installer [<command> [<args>] [<options>]]\n\n\
+    Run without a command to start the interactive prompt.\n\n\
+    Commands:\n");
+    let commands = [
+        ("backup [list]", "List the backups of the game with their disk usage."),
+        ("backup show <backup>", "Show the builds and files of the backup."),
+        ("backup verify <backup>", "Check the files of the backup against the hashes recorded when it was made."),
+        ("backup prune", "Remove old backups, keeping the newest (--keep) or recent ones (--older-than)."),
+        ("chain <update>...", "Apply several updates (changes files, update folders or archives, or folders of them) in order."),
+        ("changes", "Show the changelog."),
+        ("create <old> <new> <output>", "Create an update from the old and new game directories."),
+        ("create-reverse <output> [<backup>]", "Create an update that reverts the update of the backup (by default the latest)."),
+        ("manifest generate <dir> <output>", "Write a manifest (\".txt\" or \".sha1\") of the directory."),
+        ("merge <output> <update>...", "Merge several updates into one cumulative update in the output directory."),
+        ("resume", "Finish an interrupted update."),
+        ("rollback [<backup>]", "Revert an interrupted update, or else the update of the backup (by default the latest)."),
+        ("rollback-to <build>", "Restore the backups of the updates since the build, newest first."),
+        ("set <field> <value>", "Set the given field to the given value and show the settings."),
+        ("settings", "Show the settings."),
+        ("settings save [<file> | \"user\"]", "Save the settings in the installer folder, the file or the user settings (without paths)."),
+        ("settings load <file>", "Load the settings from the file and show them."),
+        ("settings reset", "Show the default settings."),
+        ("status [<manifest>...]", "Find the installed build by comparing the game with the manifests or folders of them."),
+        ("update", "Update the game files."),
+        ("update-to <dir> <build> [<from>]", "Update the game to the build with the fewest updates from the folder, detecting the installed build unless given."),
+        ("validate <\"update\" | \"game\">", "Validate the update files or the game files."),
+    ];
+    for (command, description) in commands {
+        usage.push_str(&format!("  {:35} {}\n", command, description));
+    }
+    usage.push_str("\nOptions:\n");
+    for (option, field) in OPTIONS {
+        let value = if field.ends_with("_file") || field.ends_with("_archive") {
+            "<file>"
+        } else if field.ends_with("_directory") {
+            "<directory>"
+        } else if field.ends_with("_threads") {
+            "<number>"
+        } else if field == "check_initial_build" {
+            "<\"refuse\" | \"warn\" | \"off\">"
+        } else if field == "backup_format" {
+            "<\"folder\" | \"zip\" | \"tar.zst\" | \"store\">"
+        } else {
+            "<bool>"
+        };
+        usage.push_str(&format!("  {:35} Set {}.\n", format!("{} {}", option, value), field));
+    }
+    for (option, description) in COMMAND_OPTIONS {
+        usage.push_str(&format!("  {:35} {}\n", format!("{} <value>", option), description));
+    }
+    usage.push_str(&format!("  {:35} {}\n", "--deltas", "Store modified files as binary patches (create)."));
+    usage.push_str(&format!("  {:35} {}\n", "--set <field>=<value>", "Set any settings field."));
+    usage.push_str(&format!("  {:35} {}\n", "--dry-run", "Show what the update would do without changing any files."));
+    usage.push_str(&format!("  {:35} {}\n", "--output <\"text\" | \"json\">", "Print text, or one JSON event per line for front-ends."));
+    usage.push_str(&format!("  {:35} {}\n", "-y, --yes", "Answer yes to all prompts."));
+    usage.push_str(&format!("  {:35} {}\n", "--ignore-validation", "Update even if the update files fail validation (with --yes)."));
+    usage.push_str(&format!("  {:35} {}\n", "--help", "Show this message."));
+    usage.push_str(&format!("  {:35} {}", "--version", "Show the version."));
+    usage
+}
+
+/// Runs a single command given on the command line and returns the exit code.
+///
+/// Exit codes are 0 on success, 1 if the command failed and 2 if the arguments were invalid.
+pub fn run(args: &[String]) -> ExitCode {
+    let usage_error = |message: String| {
+        eprintln!("{}\nType \"--help\" for usage.", message);
+        ExitCode::from(2)
+    };
+
+    // Loading the settings prints messages, so they need the output format first.
+    if let Some(format) = args.iter().rposition(|argument| argument == "--output").and_then(|index| args.get(index + 1)) {
+        output::set_json(format == "json");
+    }
+    let mut settings = Settings::load();
+    let mut positional = vec![];
+    let mut options = BTreeMap::new();
+    let mut deltas = false;
+    let mut arguments = args.iter();
+    while let Some(argument) = arguments.next() {
+        let argument = argument.as_str();
+        if argument == "-y" || argument == "--yes" {
+            settings.assume_yes = true;
+            continue;
+        }
+        if argument == "--output" {
+            match arguments.next().map(String::as_str) {
+                Some("json") => output::set_json(true),
+                Some("text") => output::set_json(false),
+                _ => return usage_error("Expected \"text\" or \"json\" for --output.".to_string()),
+            }
+            continue;
+        }
+        if argument == "--ignore-validation" {
+            settings.ignore_validation = true;
+            continue;
+        }
+        if argument == "--deltas" {
+            deltas = true;
+            continue;
+        }
+        if argument == "--dry-run" {
+            let _ = settings.set_field("dry_run", "true");
+            continue;
+        }
+        if argument == "--set" {
+            let Some(assignment) = arguments.next() else {
+                return usage_error("Missing value for --set.".to_string());
+            };
+            let Some((field, value)) = assignment.split_once('=') else {
+                return usage_error(format!("Expected <field>=<value> for --set, got \"{}\".", assignment));
+            };
+            if let Err(error) = settings.set_field(field, value) {
+                return usage_error(error.to_string());
+            }
+            continue;
+        }
+        if let Some((_, field)) = OPTIONS.iter().find(|(option, _)| *option == argument) {
+            let Some(value) = arguments.next() else {
+                return usage_error(format!("Missing value for {}.", argument));
+            };
+            if let Err(error) = settings.set_field(field, value) {
+                return usage_error(format!("{}: {}", argument, error));
+            }
+            continue;
+        }
+        if COMMAND_OPTIONS.iter().any(|(option, _)| *option == argument) {
+            let Some(value) = arguments.next() else {
+                return usage_error(format!("Missing value for {}.", argument));
+            };
+            options.insert(argument, value.clone());
+            continue;
+        }
+        if argument.starts_with("--") {
+            return usage_error(format!("Unknown option \"{}\".", argument));
+        }
+        positional.push(argument);
+    }
+
+    let result = match positional.as_slice() {
+        ["backup", arguments @ ..] => {
+            let mut arguments = arguments.to_vec();
+            for option in ["--keep", "--older-than"] {
+                if let Some(value) = options.get(option) {
+                    arguments.extend([option, value.as_str()]);
+                }
+            }
+            settings.manage_backups(&arguments)
+        },
+        ["chain", paths @ ..] if !paths.is_empty() => {
+            settings.update_chain(&paths.iter().map(PathBuf::from).collect::<Vec<PathBuf>>())
+        },
+        ["changes"] => settings.show_changes(),
+        ["create", old_directory, new_directory, output_directory] => {
+            let option = |name: &str| options.get(name).cloned().unwrap_or_default();
+            let details = Changes {
+                name: option("--name"),
+                app: option("--app"),
+                depot: option("--depot"),
+                initial_build: option("--initial-build"),
+                final_build: option("--final-build"),
+                ..Changes::default()
+            };
+            creator::create_update(Path::new(old_directory), Path::new(new_directory), Path::new(output_directory), &details, deltas)
+        },
+        ["create-reverse", output_directory, id @ ..] if id.len() <= 1 => {
+            settings.create_reverse(Path::new(output_directory), id.first().copied())
+        },
+        ["merge", output_directory, paths @ ..] if !paths.is_empty() => {
+            chain::find_chain(&paths.iter().map(PathBuf::from).collect::<Vec<PathBuf>>())
+                .and_then(|packages| creator::merge_updates(&packages, Path::new(output_directory)))
+                .map(|_| ())
+        },
+        ["manifest", "generate", directory, output] => Manifest::from_directory(Path::new(directory))
+            .and_then(|manifest| manifest.write_manifest(Path::new(output))),
+        ["resume"] => settings.resume_update(),
+        ["rollback-to", build] => settings.rollback_to(build),
+        ["rollback", id @ ..] if id.len() <= 1 => settings.rollback_game(id.first().copied()),
+        ["settings", arguments @ ..] => settings.manage_settings(arguments),
+        ["set", field, value @ ..] if !value.is_empty() => {
+            if let Err(error) = settings.set_field(field, &value.join(" ")) {
+                return usage_error(error.to_string());
+            }
+            output::message(&settings.to_string());
+            Ok(())
+        },
+        ["status", manifests @ ..] => settings.status(&manifests.iter().map(PathBuf::from).collect::<Vec<PathBuf>>()),
+        ["update"] => settings.update_game(),
+        ["update-to", library, build, installed @ ..] if installed.len() <= 1 => {
+            settings.update_to(Path::new(library), build, installed.first().copied())
+        },
+        ["validate", directory] => settings.validate(directory),
+        [] => return usage_error("Enter a command.".to_string()),
+        _ => return usage_error(format!("Invalid command \"{}\".", positional.join(" "))),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Error::Usage(message)) => usage_error(message),
+        Err(error) => {
+            if output::is_json() {
+                output::event(Event::Error { message: error.to_string() });
+            } else {
+                eprintln!("Error: {}", error);
+            }
+            ExitCode::FAILURE
+        },
+    }
+}
@@ -0,0 +1,33 @@
+//! Library behind the RedAlt SteamUp Installer, for reading and validating manifests, applying
+//! and rolling back updates and creating new ones.
+//!
+//! Every function takes its inputs as parameters and never prompts, so it can be used from other
+//! front-ends. The installer binary only adds the settings, the prompts and the command line.
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use redalt_steamup_installer::{Changes, Manifest};
+//! use redalt_steamup_installer::modules::update::{self, UpdateOptions};
+//!
+//! # fn main() -> redalt_steamup_installer::Result<()> {
+//! let game = Path::new("Game");
+//! let changes = Changes::parse_changes(Path::new("Update/.RedAlt-Steam-Installer/changes.json"))?;
+//! let options = UpdateOptions {
+//!     update_directory: Some("Update".into()),
+//!     backup_directory: Some(game.join(".Backup")),
+//!     copy_files: true,
+//!     remove_files: true,
+//!     ..UpdateOptions::default()
+//! };
+//! update::apply_update(game, update::plan_update(game, &changes, &options), None)?;
+//! Manifest::parse_manifest(Path::new("manifest.sha1"))?.validate_files(game, None, 4)?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod modules;
+
+pub use modules::changes::Changes;
+pub use modules::error::{Error, Result};
+pub use modules::journal::Journal;
+pub use modules::manifest::{GameFile, Manifest};
@@ -1,14 +1,19 @@
 use std::collections::BTreeMap;
 use std::io::{stdin, stdout, Write};
-use crate::modules::settings::Settings;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use redalt_steamup_installer::modules::{chain, creator};
+use redalt_steamup_installer::{Changes, Manifest, Result};
+use crate::settings::Settings;
 
-mod modules;
+mod cli;
+mod settings;
 
 
 const SOURCE: &str = "https://github.com/Reddiepoint/RedAlt-SteamUp-Installer";
 const DOCUMENTATION: &str = "https://reddiepoint.github.io/RedAlt-SteamUp-Documentation/using-the-installer.html";
 
-fn main() {
+fn main() -> ExitCode {
     let args = std::env::args().collect::<Vec<String>>();
     if args.len() > 1 {
         if args[1] == "--version" {
@@ -16,9 +21,11 @@ fn main() {
         } else if args[1] == "--help" {
             println!("Run the application to get started. This program requires \
             read, write and execute permissions.\n\
-            See {} for more information.", DOCUMENTATION);
+            See {} for more information.\n\n{}", DOCUMENTATION, cli::usage());
+        } else {
+            return cli::run(&args[1..]);
         }
-        return;
+        return ExitCode::SUCCESS;
     }
 
     println!("This is the companion installer for RedAlt SteamUp Creator.\n\
@@ -26,24 +33,56 @@ fn main() {
     Enter \"help\" to get a list of commands. Enter \"update\" to update the game files.\n\
     For more information, see {}.",
              env!("CARGO_PKG_VERSION"), SOURCE, DOCUMENTATION);
-    let mut settings = Settings::default();
+    let mut settings = Settings::load();
 
     println!("\n\nCurrent settings:\n{}", settings);
+    settings.check_journal();
 
     loop {
         let input = get_input(">>");
 
-        match input.as_str().split(' ').next().unwrap() {
+        let result = match input.as_str().split(' ').next().unwrap() {
+            "backup" => settings.manage_backups(&input.split(' ').skip(1).collect::<Vec<&str>>()),
+            "chain" => update_chain(&mut settings).inspect(|_| println!("Type \"exit\" to close the program.")),
             "changes" => settings.show_changes(),
+            "create" => create_update(),
+            "create-reverse" => create_reverse(&settings),
             "exit" => break,
-            "help" => get_help(input),
-            "set" => settings.modify_fields(input),
-            "settings" => println!("{}", settings),
-            "update" => settings.update_game(),
-            "validate" => settings.validate(input),
-            _ => eprintln!("Command not recognised. Type \"help\" for a list of commands."),
+            "help" => {
+                get_help(input);
+                Ok(())
+            },
+            "manifest" => generate_manifest(input),
+            "merge" => merge_updates(),
+            "resume" => settings.resume_update().inspect(|_| println!("Type \"exit\" to close the program.")),
+            "rollback" => settings.rollback_game(input.split(' ').nth(1)).inspect(|_| println!("Type \"exit\" to close the program.")),
+            "rollback-to" => {
+                let build = get_input("Build to roll back to:");
+                settings.rollback_to(&build).inspect(|_| println!("Type \"exit\" to close the program."))
+            },
+            "set" => {
+                settings.modify_fields(input);
+                Ok(())
+            },
+            "settings" => settings.manage_settings(&input.split(' ').skip(1).collect::<Vec<&str>>()),
+            "status" => {
+                println!("Enter the manifests or folders of manifests to compare the game with (leave empty to finish, \
+                or to use the manifest settings).");
+                settings.status(&get_paths("Manifest:"))
+            },
+            "update" => settings.update_game().inspect(|_| println!("Type \"exit\" to close the program.")),
+            "update-to" => update_to(&mut settings).inspect(|_| println!("Type \"exit\" to close the program.")),
+            "validate" => settings.validate(input.split(' ').nth(1).unwrap_or_default()),
+            _ => {
+                eprintln!("Command not recognised. Type \"help\" for a list of commands.");
+                Ok(())
+            },
+        };
+        if let Err(error) = result {
+            eprintln!("Error: {}", error);
         }
     }
+    ExitCode::SUCCESS
 }
 
 pub fn get_input(prompt: &str) -> String {
@@ -52,7 +91,7 @@ pub fn get_input(prompt: &str) -> String {
     stdout().flush().expect("Error: Could not flush stdout");
     stdin().read_line(&mut line).expect("Error: Could not read a line");
 
-    return line.trim().to_string()
+    line.trim().to_string()
 }
 
 fn get_help(_input: String) {
@@ -62,16 +101,98 @@ fn get_help(_input: String) {
         Some(_) => {}
     };*/
     let mut help = BTreeMap::new();
+    help.insert("backup [list]", "List the backups of the game with their disk usage.");
+    help.insert("backup show <backup>", "Show the builds and files of a backup.");
+    help.insert("backup verify <backup>", "Check the files of a backup against the hashes recorded when it was made.");
+    help.insert("backup prune", "Remove old backups: \"--keep <number>\" keeps the newest, \"--older-than <days>\" keeps recent ones.");
+    help.insert("chain", "Apply several updates in order, with a single backup and validation.");
     help.insert("changes", "Show the changelog.");
+    help.insert("create", "Create an update from two local game directories.");
+    help.insert("create-reverse", "Create an update that downgrades the game using a backup.");
     help.insert("exit", "Exit the program.");
     help.insert("help", "Show help for the given command.");
+    help.insert("manifest generate", "Generate a manifest (\".txt\" or \".sha1\") of a directory.");
+    help.insert("merge", "Merge several updates into one cumulative update.");
+    help.insert("resume", "Finish an interrupted update.");
+    help.insert("rollback [<backup>]", "Revert an interrupted update, or else the update of the given backup (by default the latest).");
+    help.insert("rollback-to", "Roll back to an earlier build by restoring the backups of the updates since then.");
     help.insert("set <field> <value>", "Set the given field to the given value. \
     To see available fields, type \"settings\".");
     help.insert("settings", "Get the current settings.");
+    help.insert("settings save [<file> | \"user\"]", "Save the settings in the installer folder, the given file or the user settings (without paths).");
+    help.insert("settings load <file>", "Load the settings from the given file.");
+    help.insert("settings reset", "Reset the settings to the defaults.");
+    help.insert("status", "Find the installed build by comparing the game with manifests.");
     help.insert("update", "Update the game files.");
+    help.insert("update-to", "Update the game to a build using the fewest updates from a folder of updates.");
     help.insert("validate <\"update\" | \"game\">", "Validate the update files or the game files.");
 
     for (key, value) in help {
-        println!("{:25} {}", key, value);
+        println!("{:35} {}", key, value);
     }
-}
\ No newline at end of file
+}
+
+fn create_update() -> Result<()> {
+    let get_path = |prompt: &str| PathBuf::from(get_input(prompt).replace('"', ""));
+    let old_directory = get_path("Old game directory:");
+    let new_directory = get_path("New game directory:");
+    let output_directory = get_path("Output directory:");
+    println!("Enter the details of the update (leave empty to skip).");
+    let details = Changes {
+        name: get_input("Name:"),
+        app: get_input("App ID:"),
+        depot: get_input("Depot ID:"),
+        initial_build: get_input("Initial build:"),
+        final_build: get_input("Final build:"),
+        ..Changes::default()
+    };
+    let deltas = matches!(get_input("Store modified files as binary patches? [y/N]:").to_lowercase().as_str(), "y" | "yes");
+    creator::create_update(&old_directory, &new_directory, &output_directory, &details, deltas)
+}
+
+fn create_reverse(settings: &Settings) -> Result<()> {
+    let output_directory = PathBuf::from(get_input("Output directory:").replace('"', ""));
+    let id = get_input("Backup (leave empty for the latest):");
+    settings.create_reverse(&output_directory, Some(id.as_str()).filter(|id| !id.is_empty()))
+}
+
+fn update_chain(settings: &mut Settings) -> Result<()> {
+    println!("Enter the changes files, update folders or archives, or folders of updates to apply (leave empty to finish).");
+    settings.update_chain(&get_paths("Update:"))
+}
+
+fn update_to(settings: &mut Settings) -> Result<()> {
+    let library = PathBuf::from(get_input("Folder of updates:").replace('"', ""));
+    let build = get_input("Build to update to:");
+    let installed = get_input("Installed build (leave empty to detect it):");
+    settings.update_to(&library, &build, Some(installed.as_str()).filter(|installed| !installed.is_empty()))
+}
+
+fn merge_updates() -> Result<()> {
+    println!("Enter the changes files, update folders or archives, or folders of updates to merge (leave empty to finish).");
+    let packages = chain::find_chain(&get_paths("Update:"))?;
+    let output_directory = PathBuf::from(get_input("Output directory:").replace('"', ""));
+    creator::merge_updates(&packages, &output_directory).map(|_| ())
+}
+
+fn get_paths(prompt: &str) -> Vec<PathBuf> {
+    let mut paths = vec![];
+    loop {
+        let path = get_input(prompt).replace('"', "");
+        if path.is_empty() {
+            break;
+        }
+        paths.push(PathBuf::from(path));
+    }
+    paths
+}
+
+fn generate_manifest(input: String) -> Result<()> {
+    if input.split(' ').nth(1) != Some("generate") {
+        eprintln!("Enter \"manifest generate\" to generate a manifest.");
+        return Ok(());
+    }
+    let directory = PathBuf::from(get_input("Directory:").replace('"', ""));
+    let output = PathBuf::from(get_input("Output file (\".txt\" or \".sha1\"):").replace('"', ""));
+    Manifest::from_directory(&directory)?.write_manifest(&output)
+}
@@ -0,0 +1,91 @@
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read};
+use std::path::Path;
+use flate2::read::GzDecoder;
+
+/// Archive formats that updates can be applied from.
+const EXTENSIONS: [&str; 5] = [".zip", ".tar", ".tar.gz", ".tgz", ".tar.zst"];
+
+pub fn is_archive(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+    EXTENSIONS.iter().any(|extension| name.ends_with(extension))
+}
+
+/// Whether the archive is a zip file, whose index lists the files without reading the whole archive.
+pub fn is_zip(path: &Path) -> bool {
+    path.file_name().unwrap_or_default().to_string_lossy().to_lowercase().ends_with(".zip")
+}
+
+/// Streams every file in the archive to the callback with its path (using "/" as separator),
+/// without extracting the archive. Directories are skipped.
+pub fn read_entries(path: &Path, mut callback: impl FnMut(&str, &mut dyn Read)) -> std::io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+    let file = BufReader::new(File::open(path)?);
+    if name.ends_with(".zip") {
+        let mut archive = zip::ZipArchive::new(file).map_err(|error| Error::new(ErrorKind::InvalidData, error))?;
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index).map_err(|error| Error::new(ErrorKind::InvalidData, error))?;
+            if entry.is_dir() {
+                continue;
+            }
+            let name = entry.name().replace('\\', "/");
+            callback(&name, &mut entry);
+        }
+        Ok(())
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        read_tar_entries(GzDecoder::new(file), callback)
+    } else if name.ends_with(".tar.zst") {
+        read_tar_entries(zstd::Decoder::with_buffer(file)?, callback)
+    } else if name.ends_with(".tar") {
+        read_tar_entries(file, callback)
+    } else {
+        Err(Error::new(ErrorKind::Unsupported, "Unsupported archive type"))
+    }
+}
+
+/// Lists the files in the archive with their uncompressed sizes.
+pub fn list_entries(path: &Path) -> std::io::Result<Vec<(String, u64)>> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+    let mut entries = vec![];
+    if name.ends_with(".zip") {
+        let file = BufReader::new(File::open(path)?);
+        let mut archive = zip::ZipArchive::new(file).map_err(|error| Error::new(ErrorKind::InvalidData, error))?;
+        for index in 0..archive.len() {
+            let entry = archive.by_index_raw(index).map_err(|error| Error::new(ErrorKind::InvalidData, error))?;
+            if !entry.is_dir() {
+                entries.push((entry.name().replace('\\', "/"), entry.size()));
+            }
+        }
+    } else {
+        read_entries(path, |name, entry| {
+            // Tar entries have to be read past to reach the next one.
+            let size = std::io::copy(entry, &mut std::io::sink()).unwrap_or(0);
+            entries.push((name.to_string(), size));
+        })?;
+    }
+    Ok(entries)
+}
+
+fn read_tar_entries(reader: impl Read, mut callback: impl FnMut(&str, &mut dyn Read)) -> std::io::Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().replace('\\', "/");
+        let name = name.trim_start_matches("./").to_string();
+        callback(&name, &mut entry);
+    }
+    Ok(())
+}
+
+/// Maps a path in the archive to the path of a file in the update, allowing the files to be
+/// inside a single top-level folder of the archive.
+pub fn find_update_path<'a>(name: &str, paths: &'a [String]) -> Option<&'a String> {
+    paths.iter().find(|path| *path == name)
+        .or_else(|| {
+            let (_, name) = name.split_once('/')?;
+            paths.iter().find(|path| *path == name)
+        })
+}
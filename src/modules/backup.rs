@@ -0,0 +1,533 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::fs::{create_dir_all, metadata, read_dir, remove_dir_all, remove_file, rename, File};
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::modules::archive::read_entries;
+use crate::modules::changes::Changes;
+use crate::modules::creator::INSTALLER_DIRECTORY;
+use crate::modules::error::{Error, Result};
+use crate::modules::files::{copy_and_hash, hash_file, list_files};
+use crate::modules::manifest::{summary, GameFile, Manifest};
+use crate::modules::output::{self, Status, Task};
+
+/// Folder in the game directory that holds every backup.
+pub const BACKUP_DIRECTORY: &str = ".Backup";
+
+/// Index of the backups, in the backup folder.
+const INDEX_FILE: &str = "index.json";
+
+/// Folder in the backup folder that stores the contents of backed-up files by SHA-1, shared by
+/// all backups in the store format.
+const OBJECTS_DIRECTORY: &str = "objects";
+
+/// Version of the backup index written by this installer.
+const VERSION: u32 = 1;
+
+/// How a backup is stored once its update has finished.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub enum BackupFormat {
+    /// The files as they were, in a folder.
+    #[default]
+    #[serde(rename = "folder")]
+    Folder,
+    #[serde(rename = "zip")]
+    Zip,
+    /// A tar archive compressed with zstd.
+    #[serde(rename = "tar.zst")]
+    TarZst,
+    /// The contents of the files in the object store, so content that is already backed up is
+    /// not stored again. The folder keeps the changes and a manifest of the backed-up files.
+    #[serde(rename = "store")]
+    Store,
+}
+
+impl Display for BackupFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            BackupFormat::Folder => "folder",
+            BackupFormat::Zip => "zip",
+            BackupFormat::TarZst => "tar.zst",
+            BackupFormat::Store => "store",
+        })
+    }
+}
+
+/// Files replaced or removed by one update, kept in their own folder so later updates never
+/// overwrite them.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Backup {
+    /// Name of the folder of the backup, made from the app, depot, builds and creation time.
+    pub id: String,
+    pub app: String,
+    pub depot: String,
+    pub initial_build: String,
+    pub final_build: String,
+    /// Seconds since the Unix epoch.
+    pub created: u64,
+    /// Changes file of the update. A copy is kept in the installer folder of the backup.
+    pub changes_file: Option<PathBuf>,
+    #[serde(default)]
+    pub format: BackupFormat,
+    pub files: Vec<BackupFile>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct BackupFile {
+    pub path: String,
+    pub sha1: String,
+    pub size: u64,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct BackupIndex {
+    #[serde(default)]
+    pub version: u32,
+    pub backups: Vec<Backup>,
+}
+
+impl Backup {
+    /// Describes the backup of the update, named after its builds and the current time. A number
+    /// is added to the name if the game already has a backup with it.
+    pub fn new(game_directory: &Path, changes: &Changes, changes_file: Option<&Path>) -> Result<Backup> {
+        let created = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+        let part = |value: &str| match value.trim() {
+            "" => "unknown".to_string(),
+            value => value.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '_' }).collect(),
+        };
+        let name = format!("{}_{}_{}-{}_{}", part(&changes.app), part(&changes.depot), part(&changes.initial_build),
+                           part(&changes.final_build), format_timestamp(created));
+        let index = BackupIndex::load(game_directory)?;
+        let backup_directory = game_directory.join(BACKUP_DIRECTORY);
+        let is_taken = |id: &str| index.find(id).is_some()
+            || [id.to_string(), format!("{}.{}", id, BackupFormat::Zip), format!("{}.{}", id, BackupFormat::TarZst)]
+                .iter()
+                .any(|name| backup_directory.join(name).exists());
+        let id = (1..).map(|number| if number == 1 { name.clone() } else { format!("{}-{}", name, number) })
+            .find(|id| !is_taken(id))
+            .unwrap();
+        Ok(Backup {
+            id,
+            app: changes.app.clone(),
+            depot: changes.depot.clone(),
+            initial_build: changes.initial_build.clone(),
+            final_build: changes.final_build.clone(),
+            created,
+            changes_file: changes_file.map(|file| std::path::absolute(file).unwrap_or(file.to_path_buf())),
+            format: BackupFormat::Folder,
+            files: vec![],
+        })
+    }
+
+    /// Folder the files are backed up to during the update.
+    pub fn directory(&self, game_directory: &Path) -> PathBuf {
+        game_directory.join(BACKUP_DIRECTORY).join(&self.id)
+    }
+
+    /// Folder or archive holding the backup.
+    pub fn location(&self, game_directory: &Path) -> PathBuf {
+        match self.format {
+            BackupFormat::Folder | BackupFormat::Store => self.directory(game_directory),
+            format => game_directory.join(BACKUP_DIRECTORY).join(format!("{}.{}", self.id, format)),
+        }
+    }
+
+    /// Returns where the backup of the game file is, unless the backup is an archive or does not
+    /// have the file.
+    pub fn backup_file(&self, game_directory: &Path, path: &str) -> Option<PathBuf> {
+        match self.format {
+            BackupFormat::Folder => Some(self.directory(game_directory).join(path)),
+            BackupFormat::Store => self.files.iter()
+                .find(|file| file.path == path)
+                .map(|file| object_path(game_directory, &file.sha1)),
+            _ => None,
+        }
+    }
+
+    /// Reads the copy of the changes of the update kept with the backup.
+    pub fn changes(&self, game_directory: &Path) -> Result<Changes> {
+        let changes_file = Path::new(INSTALLER_DIRECTORY).join("changes.json");
+        if matches!(self.format, BackupFormat::Folder | BackupFormat::Store) {
+            return Changes::parse_changes(&self.directory(game_directory).join(changes_file));
+        }
+        let archive = self.location(game_directory);
+        let mut changes = None;
+        read_entries(&archive, |name, entry| {
+            if changes.is_none() && name == format!("{}/changes.json", INSTALLER_DIRECTORY) {
+                let mut contents = String::new();
+                changes = Some(entry.read_to_string(&mut contents).map(|_| contents));
+            }
+        }).map_err(Error::io(&archive))?;
+        let changes = changes.ok_or(Error::MissingSource(format!("{} (not in {})", changes_file.display(), archive.display())))?
+            .map_err(Error::io(&archive))?;
+        serde_json::from_str(&changes).map_err(|error| Error::Parse { path: archive.join(changes_file), message: error.to_string() })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.files.iter().map(|file| file.size).sum()
+    }
+}
+
+impl BackupIndex {
+    /// Reads the backup index of the game directory, which is empty if no backups were made.
+    pub fn load(game_directory: &Path) -> Result<BackupIndex> {
+        let path = game_directory.join(BACKUP_DIRECTORY).join(INDEX_FILE);
+        if !path.is_file() {
+            return Ok(BackupIndex::default());
+        }
+        let index = std::fs::read_to_string(&path).map_err(Error::io(&path))?;
+        serde_json::from_str(&index).map_err(|error| Error::Parse { path, message: error.to_string() })
+    }
+
+    pub fn save(&mut self, game_directory: &Path) -> Result<()> {
+        let path = game_directory.join(BACKUP_DIRECTORY).join(INDEX_FILE);
+        self.version = VERSION;
+        // The index only holds strings and numbers.
+        let index = serde_json::to_string_pretty(self).unwrap();
+        std::fs::write(&path, index).map_err(Error::io(&path))
+    }
+
+    pub fn find(&self, id: &str) -> Option<&Backup> {
+        self.backups.iter().find(|backup| backup.id == id)
+    }
+
+    pub fn latest(&self) -> Option<&Backup> {
+        self.backups.iter().max_by_key(|backup| backup.created)
+    }
+}
+
+/// Checks for files backed up before the backup index, which are kept directly in the backup
+/// folder rather than in folders of their own.
+pub fn has_legacy_backup(game_directory: &Path, index: &BackupIndex) -> Result<bool> {
+    let backup_directory = game_directory.join(BACKUP_DIRECTORY);
+    if !backup_directory.is_dir() {
+        return Ok(false);
+    }
+    let indexed: HashSet<PathBuf> = index.backups.iter().map(|backup| backup.location(game_directory)).collect();
+    for entry in read_dir(&backup_directory).map_err(Error::io(&backup_directory))? {
+        let path = entry.map_err(Error::io(&backup_directory))?.path();
+        let name = path.file_name().unwrap_or_default();
+        if name != INDEX_FILE && name != OBJECTS_DIRECTORY && !indexed.contains(&path) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Creates the folder of the backup and adds it to the index. The changes are copied into the
+/// folder, so the backup can be restored without the original changes file.
+pub fn create_backup(game_directory: &Path, backup: &Backup, changes: &Changes) -> Result<()> {
+    let changes_copy = backup.directory(game_directory).join(INSTALLER_DIRECTORY).join("changes.json");
+    create_dir_all(changes_copy.parent().unwrap()).map_err(Error::io(changes_copy.parent().unwrap()))?;
+    changes.write_changes(&changes_copy)?;
+    let mut index = BackupIndex::load(game_directory)?;
+    index.backups.push(backup.clone());
+    index.save(game_directory)
+}
+
+/// Records the files in the folder of the backup in the index, with their hashes and sizes.
+/// Returns whether the backup is in the index.
+pub fn index_backup(game_directory: &Path, id: &str) -> Result<bool> {
+    let mut index = BackupIndex::load(game_directory)?;
+    let Some(backup) = index.backups.iter_mut().find(|backup| backup.id == id) else {
+        return Ok(false);
+    };
+    let directory = backup.directory(game_directory);
+    let mut files = vec![];
+    for path in list_files(&directory).map_err(Error::io(&directory))? {
+        let file = directory.join(&path);
+        files.push(BackupFile {
+            sha1: hash_file(&file).map_err(Error::io(&file))?,
+            size: metadata(&file).map_err(Error::io(&file))?.len(),
+            path,
+        });
+    }
+    backup.files = files;
+    index.save(game_directory)?;
+    Ok(true)
+}
+
+/// Moves the folder of a finished backup into the format, returning the location of the backup.
+/// Archives replace the folder, while the store takes the files out of it. Backups already
+/// moved are left as they are.
+pub fn store_backup(game_directory: &Path, id: &str, format: BackupFormat) -> Result<PathBuf> {
+    let mut index = BackupIndex::load(game_directory)?;
+    let Some(backup) = index.backups.iter_mut().find(|backup| backup.id == id) else {
+        return Ok(game_directory.join(BACKUP_DIRECTORY).join(id));
+    };
+    if format == BackupFormat::Folder || backup.format != BackupFormat::Folder {
+        return Ok(backup.location(game_directory));
+    }
+
+    let directory = backup.directory(game_directory);
+    backup.format = format;
+    if format == BackupFormat::Store {
+        add_objects(game_directory, backup)?;
+        index.save(game_directory)?;
+        return Ok(directory);
+    }
+    let archive = backup.location(game_directory);
+    output::message(&format!("Compressing the backup to {}.", archive.display()));
+    let mut paths = list_files(&directory).map_err(Error::io(&directory))?;
+    paths.push(format!("{}/changes.json", INSTALLER_DIRECTORY));
+    if let Err(error) = write_archive(&directory, &paths, &archive, format) {
+        let _ = remove_file(&archive);
+        return Err(Error::io(&archive)(error));
+    }
+    // The index only points to the archive once it is complete.
+    index.save(game_directory)?;
+    remove_dir_all(&directory).map_err(Error::io(&directory))?;
+    Ok(archive)
+}
+
+/// Moves the files of the backup folder into the object store, deleting those whose content is
+/// already stored, and writes the manifest of the backed-up files into the folder.
+fn add_objects(game_directory: &Path, backup: &Backup) -> Result<()> {
+    let directory = backup.directory(game_directory);
+    output::message(&format!("Adding the backup to {}.", game_directory.join(BACKUP_DIRECTORY).join(OBJECTS_DIRECTORY).display()));
+    let mut stored = 0;
+    for file in &backup.files {
+        let source = directory.join(&file.path);
+        let object = object_path(game_directory, &file.sha1);
+        if object.is_file() {
+            remove_file(&source).map_err(Error::io(&source))?;
+            continue;
+        }
+        create_dir_all(object.parent().unwrap()).map_err(Error::io(object.parent().unwrap()))?;
+        rename(&source, &object).map_err(Error::io(&source))?;
+        stored += 1;
+    }
+    output::message(&format!("Stored {} new files, {} were already in the store.", stored, backup.files.len() - stored));
+
+    // Only the installer folder is left, so the folders the files were in can go.
+    for entry in read_dir(&directory).map_err(Error::io(&directory))? {
+        let entry = entry.map_err(Error::io(&directory))?;
+        if entry.file_name() != INSTALLER_DIRECTORY && entry.path().is_dir() {
+            remove_dir_all(entry.path()).map_err(Error::io(&entry.path()))?;
+        }
+    }
+    let manifest = Manifest::new(backup.files.iter().map(|file| GameFile {
+        hash: file.sha1.clone(),
+        name: file.path.clone(),
+        size: Some(file.size),
+    }).collect());
+    manifest.write_manifest(&directory.join(INSTALLER_DIRECTORY).join("manifest.sha1"))
+}
+
+/// Returns the path of the content with the hash in the object store.
+fn object_path(game_directory: &Path, sha1: &str) -> PathBuf {
+    let sha1 = sha1.to_lowercase();
+    game_directory.join(BACKUP_DIRECTORY).join(OBJECTS_DIRECTORY).join(&sha1[..2.min(sha1.len())]).join(&sha1)
+}
+
+/// Deletes the objects that no backup in the index refers to, returning the space freed.
+fn collect_garbage(game_directory: &Path, index: &BackupIndex) -> Result<u64> {
+    let objects = game_directory.join(BACKUP_DIRECTORY).join(OBJECTS_DIRECTORY);
+    if !objects.is_dir() {
+        return Ok(0);
+    }
+    let referenced: HashSet<String> = index.backups.iter()
+        .filter(|backup| backup.format == BackupFormat::Store)
+        .flat_map(|backup| backup.files.iter().map(|file| file.sha1.to_lowercase()))
+        .collect();
+    let mut freed = 0;
+    for path in list_files(&objects).map_err(Error::io(&objects))? {
+        let name = path.rsplit('/').next().unwrap_or_default();
+        if !referenced.contains(name) {
+            let object = objects.join(&path);
+            freed += metadata(&object).map_err(Error::io(&object))?.len();
+            remove_file(&object).map_err(Error::io(&object))?;
+            let _ = std::fs::remove_dir(object.parent().unwrap());
+        }
+    }
+    Ok(freed)
+}
+
+fn write_archive(directory: &Path, paths: &[String], archive: &Path, format: BackupFormat) -> std::io::Result<()> {
+    let output = BufWriter::new(File::create(archive)?);
+    match format {
+        BackupFormat::Zip => {
+            let mut zip = zip::ZipWriter::new(output);
+            for path in paths {
+                let mut file = File::open(directory.join(path))?;
+                let options = zip::write::SimpleFileOptions::default()
+                    .compression_method(zip::CompressionMethod::Deflated)
+                    .large_file(file.metadata()?.len() >= u32::MAX as u64);
+                zip.start_file(path.as_str(), options).map_err(std::io::Error::other)?;
+                std::io::copy(&mut file, &mut zip)?;
+            }
+            zip.finish().map_err(std::io::Error::other)?.flush()
+        },
+        BackupFormat::TarZst => {
+            let mut tar = tar::Builder::new(zstd::Encoder::new(output, zstd::DEFAULT_COMPRESSION_LEVEL)?);
+            for path in paths {
+                tar.append_path_with_name(directory.join(path), path)?;
+            }
+            tar.into_inner()?.finish()?.flush()
+        },
+        BackupFormat::Folder | BackupFormat::Store => Ok(()),
+    }
+}
+
+/// Deletes the folder or archive of the backup and removes it from the index.
+pub fn remove_backup(game_directory: &Path, id: &str) -> Result<()> {
+    let mut index = BackupIndex::load(game_directory)?;
+    let directory = game_directory.join(BACKUP_DIRECTORY).join(id);
+    if directory.is_dir() {
+        remove_dir_all(&directory).map_err(Error::io(&directory))?;
+    }
+    if let Some(backup) = index.find(id) {
+        let archive = backup.location(game_directory);
+        if archive.is_file() {
+            remove_file(&archive).map_err(Error::io(&archive))?;
+        }
+    }
+    index.backups.retain(|backup| backup.id != id);
+    index.save(game_directory)?;
+    collect_garbage(game_directory, &index).map(|_| ())
+}
+
+/// Re-hashes the files of the backup and compares them with the hashes recorded in the index.
+pub fn verify_backup(game_directory: &Path, backup: &Backup) -> Result<()> {
+    let location = backup.location(game_directory);
+    output::message(&format!("Verifying {}", location.display()));
+    // Archives are read once, hashing every file in them.
+    let mut archived = HashMap::new();
+    if matches!(backup.format, BackupFormat::Zip | BackupFormat::TarZst) {
+        read_entries(&location, |name, entry| {
+            archived.insert(name.to_string(), copy_and_hash(entry, &mut std::io::sink()));
+        }).map_err(Error::io(&location))?;
+    }
+
+    let (mut successes, mut bytes) = (0, 0);
+    let (mut mismatched, mut missing) = (vec![], vec![]);
+    for file in &backup.files {
+        let hash = match backup.format {
+            BackupFormat::Folder => hash_file(&location.join(&file.path)),
+            BackupFormat::Store => hash_file(&object_path(game_directory, &file.sha1)),
+            _ => archived.remove(&file.path).unwrap_or_else(|| Err(ErrorKind::NotFound.into())),
+        };
+        match hash {
+            Ok(hash) if hash.eq_ignore_ascii_case(&file.sha1) => {
+                output::file(Task::Validate, &file.path, Status::Ok, Some(file.size), None,
+                             format_args!("Verifying {}...\tOk.", file.path));
+                successes += 1;
+                bytes += file.size;
+            },
+            Ok(_) => {
+                output::file(Task::Validate, &file.path, Status::Mismatch, None, None,
+                             format_args!("Verifying {}...\tHash mismatch.", file.path));
+                mismatched.push(file.path.clone());
+            },
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                output::file(Task::Validate, &file.path, Status::Missing, None, None,
+                             format_args!("Verifying {}...\tMissing.", file.path));
+                missing.push(file.path.clone());
+            },
+            Err(error) => {
+                output::file(Task::Validate, &file.path, Status::Error, None, Some(error.to_string()),
+                             format_args!("Verifying {}...\tError: {}", file.path, error));
+                mismatched.push(file.path.clone());
+            },
+        }
+    }
+
+    summary(backup.files.len(), successes, &mismatched, &missing, bytes);
+    if !mismatched.is_empty() || !missing.is_empty() {
+        Err(Error::Validation { mismatched, missing })
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns the backups that pruning would remove, from the oldest to the newest. A backup is
+/// kept if it is one of the newest `keep` backups or if it is newer than `older_than` seconds, so
+/// it is only removed if every given rule allows it.
+pub fn backups_to_prune(index: &BackupIndex, keep: Option<usize>, older_than: Option<u64>) -> Vec<Backup> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+    let mut backups = index.backups.clone();
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.created));
+    let mut pruned: Vec<Backup> = backups.into_iter().enumerate()
+        .filter(|(position, backup)| {
+            keep.is_none_or(|keep| *position >= keep)
+                && older_than.is_none_or(|age| now.saturating_sub(backup.created) > age)
+        })
+        .map(|(_, backup)| backup)
+        .collect();
+    pruned.reverse();
+    pruned
+}
+
+/// Returns the space taken by the backup on disk. Objects in the store only count for the backup
+/// if no other backup refers to them, as removing it would not free them.
+pub fn disk_usage(game_directory: &Path, index: &BackupIndex, backup: &Backup) -> Result<u64> {
+    let location = backup.location(game_directory);
+    match backup.format {
+        BackupFormat::Folder => directory_size(&location),
+        BackupFormat::Store => {
+            let shared: HashSet<&str> = index.backups.iter()
+                .filter(|other| other.format == BackupFormat::Store && other.id != backup.id)
+                .flat_map(|other| other.files.iter().map(|file| file.sha1.as_str()))
+                .collect();
+            let mut unique = HashSet::new();
+            let mut size = directory_size(&location)?;
+            for file in backup.files.iter().filter(|file| !shared.contains(file.sha1.as_str())) {
+                if unique.insert(&file.sha1) {
+                    size += file.size;
+                }
+            }
+            Ok(size)
+        },
+        _ => Ok(metadata(&location).map_err(Error::io(&location))?.len()),
+    }
+}
+
+/// Returns the space taken by all backups on disk.
+pub fn total_disk_usage(game_directory: &Path) -> Result<u64> {
+    let directory = game_directory.join(BACKUP_DIRECTORY);
+    if !directory.is_dir() {
+        return Ok(0);
+    }
+    directory_size(&directory)
+}
+
+fn directory_size(directory: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in read_dir(directory).map_err(Error::io(directory))? {
+        let entry = entry.map_err(Error::io(directory))?;
+        let metadata = entry.metadata().map_err(Error::io(&entry.path()))?;
+        size += if metadata.is_dir() { directory_size(&entry.path())? } else { metadata.len() };
+    }
+    Ok(size)
+}
+
+/// Formats seconds since the Unix epoch as a UTC date and time for display, e.g.
+/// "2026-10-17 02:41:00 UTC".
+pub fn format_date(seconds: u64) -> String {
+    let [year, month, day, hour, minute, second] = civil_time(seconds);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, hour, minute, second)
+}
+
+/// Formats seconds since the Unix epoch as a UTC date and time for file names, e.g. "20261017-024100".
+pub fn format_timestamp(seconds: u64) -> String {
+    let [year, month, day, hour, minute, second] = civil_time(seconds);
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, hour, minute, second)
+}
+
+/// Splits seconds since the Unix epoch into the UTC year, month, day, hour, minute and second.
+fn civil_time(seconds: u64) -> [i64; 6] {
+    let (days, time) = ((seconds / 86400) as i64, (seconds % 86400) as i64);
+    // Converts days since 1970-01-01 to a civil date (proleptic Gregorian calendar).
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    [year, month, day, time / 3600, time / 60 % 60, time % 60]
+}
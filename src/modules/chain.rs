@@ -0,0 +1,238 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+use crate::modules::archive::{find_update_path, is_archive, read_entries};
+use crate::modules::changes::Changes;
+use crate::modules::creator::INSTALLER_DIRECTORY;
+use crate::modules::detect::Fingerprint;
+use crate::modules::error::{Error, Result};
+
+/// An update with its changes and the folder or archive holding its new files.
+#[derive(Clone)]
+pub struct Package {
+    pub changes: Changes,
+    pub update_directory: Option<PathBuf>,
+    pub update_archive: Option<PathBuf>,
+}
+
+impl Package {
+    /// Folder or archive the package was found in, for display.
+    pub fn location(&self) -> &Path {
+        self.update_archive.as_ref().or(self.update_directory.as_ref()).unwrap()
+    }
+}
+
+/// Finds the updates at the path, which is a changes file, an update folder or archive with the
+/// changes file in its installer folder, or a folder holding several of them.
+pub fn find_packages(path: &Path) -> Result<Vec<Package>> {
+    if let Some(package) = read_package(path)? {
+        return Ok(vec![package]);
+    }
+    if path.is_file() {
+        return Err(Error::InvalidPath { path: path.to_path_buf(), expected: "a changes file or an update archive with one" });
+    }
+
+    let mut entries: Vec<PathBuf> = read_dir(path).map_err(Error::io(path))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    entries.sort();
+    let mut packages = vec![];
+    for entry in entries {
+        if entry.is_dir() || is_archive(&entry) {
+            packages.extend(read_package(&entry)?);
+        }
+    }
+    if packages.is_empty() {
+        return Err(Error::InvalidPath { path: path.to_path_buf(), expected: "an update or a folder of updates" });
+    }
+    Ok(packages)
+}
+
+/// Reads the package at the path, or returns `None` if the path is a folder without a changes
+/// file or an archive without one.
+fn read_package(path: &Path) -> Result<Option<Package>> {
+    let changes_file = Path::new(INSTALLER_DIRECTORY).join("changes.json");
+    if path.is_dir() {
+        if !path.join(&changes_file).is_file() {
+            return Ok(None);
+        }
+        return Ok(Some(Package {
+            changes: Changes::parse_changes(&path.join(&changes_file))?,
+            update_directory: Some(path.to_path_buf()),
+            update_archive: None,
+        }));
+    }
+
+    if is_archive(path) {
+        let changes_paths = [format!("{}/changes.json", INSTALLER_DIRECTORY)];
+        let mut changes = None;
+        read_entries(path, |name, entry| {
+            if changes.is_none() && find_update_path(name, &changes_paths).is_some() {
+                let mut contents = String::new();
+                changes = Some(entry.read_to_string(&mut contents).map(|_| contents));
+            }
+        }).map_err(Error::io(path))?;
+        let Some(changes) = changes else {
+            return Ok(None);
+        };
+        let changes = changes.map_err(Error::io(path))?;
+        return Ok(Some(Package {
+            changes: serde_json::from_str(&changes).map_err(|error| Error::Parse {
+                path: path.join(changes_file),
+                message: error.to_string(),
+            })?,
+            update_directory: None,
+            update_archive: Some(path.to_path_buf()),
+        }));
+    }
+
+    if !path.is_file() {
+        return Ok(None);
+    }
+    // The new files are next to the installer folder holding the changes file, or else next to
+    // the changes file.
+    let parent = path.parent().unwrap_or(Path::new("."));
+    let update_directory = match parent.file_name() {
+        Some(name) if name == INSTALLER_DIRECTORY => parent.parent().unwrap_or(Path::new(".")),
+        _ => parent,
+    };
+    Ok(Some(Package {
+        changes: Changes::parse_changes(path)?,
+        update_directory: Some(update_directory.to_path_buf()),
+        update_archive: None,
+    }))
+}
+
+/// Finds the updates at all the paths and orders them into a chain.
+pub fn find_chain(paths: &[PathBuf]) -> Result<Vec<Package>> {
+    let mut packages = vec![];
+    for path in paths {
+        packages.extend(find_packages(path)?);
+    }
+    order_packages(packages)
+}
+
+/// Orders the updates from the oldest to the newest build and checks that each one starts at the
+/// build the one before it ends at.
+pub fn order_packages(mut packages: Vec<Package>) -> Result<Vec<Package>> {
+    if packages.is_empty() {
+        return Err(Error::NoUpdates);
+    }
+    // Steam builds are numbers, but other builds are still ordered by name.
+    packages.sort_by_key(|package| {
+        let build = &package.changes.initial_build;
+        (build.parse::<u64>().ok(), build.clone())
+    });
+    for pair in packages.windows(2) {
+        if pair[0].changes.final_build != pair[1].changes.initial_build || pair[0].changes.final_build.is_empty() {
+            return Err(Error::BrokenChain {
+                final_build: pair[0].changes.final_build.clone(),
+                initial_build: pair[1].changes.initial_build.clone(),
+            });
+        }
+    }
+    Ok(packages)
+}
+
+/// Finds the fewest updates that take the game from one build to another. Cumulative updates
+/// spanning several builds count as a single step.
+pub fn plan_path(packages: &[Package], initial_build: &str, final_build: &str) -> Result<Vec<Package>> {
+    // The update that first reached each build, searching breadth first.
+    let mut reached_by: HashMap<&str, usize> = HashMap::new();
+    let mut seen = HashSet::from([initial_build]);
+    let mut queue = VecDeque::from([initial_build]);
+    while let Some(build) = queue.pop_front() {
+        if build == final_build {
+            break;
+        }
+        for (index, package) in packages.iter().enumerate() {
+            let next = package.changes.final_build.as_str();
+            if package.changes.initial_build == build && seen.insert(next) {
+                reached_by.insert(next, index);
+                queue.push_back(next);
+            }
+        }
+    }
+    if !seen.contains(final_build) {
+        return Err(Error::NoUpdatePath { initial_build: initial_build.to_string(), final_build: final_build.to_string() });
+    }
+
+    let mut path = vec![];
+    let mut build = final_build;
+    while build != initial_build {
+        let package = &packages[reached_by[build]];
+        path.push(package.clone());
+        build = &package.changes.initial_build;
+    }
+    path.reverse();
+    Ok(path)
+}
+
+/// Returns what the updates tell about each build they start or end at.
+pub fn fingerprints(packages: &[Package]) -> Vec<Fingerprint> {
+    let mut builds: BTreeMap<String, Fingerprint> = BTreeMap::new();
+    for package in packages {
+        let (initial, last) = package.changes.fingerprints();
+        for fingerprint in [initial, last] {
+            builds.entry(fingerprint.build.clone()).or_default().extend(fingerprint);
+        }
+    }
+    builds.into_iter()
+        .filter(|(build, _)| !build.is_empty())
+        .map(|(build, fingerprint)| Fingerprint { build, ..fingerprint })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(initial_build: &str, final_build: &str) -> Package {
+        Package {
+            changes: Changes {
+                initial_build: initial_build.to_string(),
+                final_build: final_build.to_string(),
+                ..Changes::default()
+            },
+            update_directory: Some(PathBuf::from(format!("{}-{}", initial_build, final_build))),
+            update_archive: None,
+        }
+    }
+
+    fn steps(path: &[Package]) -> Vec<String> {
+        path.iter().map(|package| package.location().display().to_string()).collect()
+    }
+
+    #[test]
+    fn follows_consecutive_updates() {
+        let packages = [package("2", "3"), package("1", "2"), package("3", "4")];
+        assert_eq!(steps(&plan_path(&packages, "1", "4").unwrap()), ["1-2", "2-3", "3-4"]);
+        assert_eq!(steps(&plan_path(&packages, "2", "3").unwrap()), ["2-3"]);
+    }
+
+    #[test]
+    fn prefers_cumulative_updates() {
+        let packages = [package("1", "2"), package("2", "3"), package("3", "4"), package("1", "3")];
+        assert_eq!(steps(&plan_path(&packages, "1", "4").unwrap()), ["1-3", "3-4"]);
+    }
+
+    #[test]
+    fn nothing_to_do_at_the_final_build() {
+        let packages = [package("1", "2")];
+        assert!(plan_path(&packages, "2", "2").unwrap().is_empty());
+    }
+
+    #[test]
+    fn downgrades_do_not_loop() {
+        let packages = [package("1", "2"), package("2", "1"), package("2", "3")];
+        assert_eq!(steps(&plan_path(&packages, "1", "3").unwrap()), ["1-2", "2-3"]);
+        assert_eq!(steps(&plan_path(&packages, "2", "1").unwrap()), ["2-1"]);
+    }
+
+    #[test]
+    fn missing_updates_have_no_path() {
+        let packages = [package("1", "2"), package("3", "4")];
+        assert!(matches!(plan_path(&packages, "1", "4"), Err(Error::NoUpdatePath { .. })));
+        assert!(matches!(plan_path(&packages, "4", "1"), Err(Error::NoUpdatePath { .. })));
+    }
+}
@@ -1,48 +1,407 @@
-use std::path::PathBuf;
-use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::modules::delta::Delta;
+use crate::modules::detect::Fingerprint;
+use crate::modules::error::{Error, Result};
+use crate::modules::manifest::{GameFile, Manifest};
 
-#[derive(Clone, Default, Deserialize)]
+/// Version of the changes file written by this installer.
+const VERSION: u32 = 2;
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(try_from = "ChangesFile", into = "ChangesFile")]
 pub struct Changes {
-    #[serde(default)]
     pub name: String,
-    #[serde(default)]
     pub app: String,
-    #[serde(default)]
     pub depot: String,
-    #[serde(default)]
     pub initial_build: String,
-    #[serde(default)]
     pub final_build: String,
     pub added: Vec<String>,
     pub removed: Vec<String>,
     pub modified: Vec<String>,
+    pub manifest: String,
+    /// Hashes and sizes of the changed files, which older changes files do not have.
+    pub files: BTreeMap<String, FileInfo>,
+}
+
+/// SHA-1 and size of a changed file at the initial build (modified and removed files) and at the
+/// final build (added and modified files).
+#[derive(Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct FileInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_sha1: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_sha1: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_size: Option<u64>,
+    /// Path of a binary patch in the update that turns the old file into the new one, which
+    /// replaces or accompanies the full new file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delta: Option<String>,
+}
+
+/// Layout of the changes file. Version 1 lists the paths of the changed files, version 2 lists
+/// entries with the path, hashes and size of each file.
+#[derive(Deserialize, Serialize)]
+struct ChangesFile {
+    #[serde(default = "first_version")]
+    version: u32,
     #[serde(default)]
-    pub manifest: String
+    name: String,
+    #[serde(default)]
+    app: String,
+    #[serde(default)]
+    depot: String,
+    #[serde(default)]
+    initial_build: String,
+    #[serde(default)]
+    final_build: String,
+    added: Vec<Entry>,
+    removed: Vec<Entry>,
+    modified: Vec<Entry>,
+    #[serde(default)]
+    manifest: String,
 }
 
-impl Changes {
-    pub fn parse_changes(path: &Option<PathBuf>) -> Option<Changes> {
-        let changes = match &path {
-            Some(file) => {
-                match std::fs::read_to_string(file) {
-                    Ok(changes) => changes,
-                    Err(error) => {
-                        eprintln!("Error reading changes file: {}", error);
-                        return None;
-                    }
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum Entry {
+    Path(String),
+    File {
+        path: String,
+        #[serde(flatten)]
+        info: FileInfo,
+    },
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+impl TryFrom<ChangesFile> for Changes {
+    type Error = String;
+
+    fn try_from(file: ChangesFile) -> std::result::Result<Self, Self::Error> {
+        if file.version > VERSION {
+            return Err(format!("Unsupported changes file version {} (the newest supported version is {})", file.version, VERSION));
+        }
+        let mut files = BTreeMap::new();
+        let mut paths = |entries: Vec<Entry>| entries.into_iter()
+            .map(|entry| match entry {
+                Entry::Path(path) => path,
+                Entry::File { path, info } => {
+                    files.insert(path.clone(), info);
+                    path
                 }
-            },
-            None => {
-                eprintln!("Provide a changes file.");
-                return None;
+            })
+            .collect::<Vec<String>>();
+        let added = paths(file.added);
+        let removed = paths(file.removed);
+        let modified = paths(file.modified);
+        Ok(Changes {
+            name: file.name,
+            app: file.app,
+            depot: file.depot,
+            initial_build: file.initial_build,
+            final_build: file.final_build,
+            added,
+            removed,
+            modified,
+            manifest: file.manifest,
+            files,
+        })
+    }
+}
+
+impl From<Changes> for ChangesFile {
+    fn from(changes: Changes) -> Self {
+        let entries = |paths: Vec<String>| paths.into_iter()
+            .map(|path| Entry::File {
+                info: changes.files.get(&path).cloned().unwrap_or_default(),
+                path,
+            })
+            .collect();
+        ChangesFile {
+            version: VERSION,
+            added: entries(changes.added),
+            removed: entries(changes.removed),
+            modified: entries(changes.modified),
+            name: changes.name,
+            app: changes.app,
+            depot: changes.depot,
+            initial_build: changes.initial_build,
+            final_build: changes.final_build,
+            manifest: changes.manifest,
+        }
+    }
+}
+
+impl Changes {
+    /// Reads a changes file (JSON) as written by the creator, in any supported version.
+    pub fn parse_changes(path: &Path) -> Result<Changes> {
+        let changes = std::fs::read_to_string(path).map_err(Error::io(path))?;
+        serde_json::from_str::<Changes>(&changes).map_err(|error| Error::Parse {
+            path: path.to_path_buf(),
+            message: error.to_string(),
+        })
+    }
+
+    /// Writes the changes as a JSON file in the current version.
+    pub fn write_changes(&self, path: &Path) -> Result<()> {
+        // Serialising plain strings and lists cannot fail.
+        let changes = serde_json::to_string_pretty(self).unwrap();
+        std::fs::write(path, changes).map_err(Error::io(path))
+    }
+
+    /// Returns the expected hashes of the added and modified files after the update, if the
+    /// changes file has them, so they can be validated without a separate manifest.
+    pub fn final_manifest(&self) -> Option<Manifest> {
+        let files: Vec<GameFile> = self.added.iter().chain(&self.modified)
+            .filter_map(|path| {
+                let info = self.files.get(path)?;
+                Some(GameFile {
+                    size: info.new_size,
+                    ..GameFile::new(info.new_sha1.clone()?, path.clone())
+                })
+            })
+            .collect();
+        (!files.is_empty()).then(|| Manifest::new(files))
+    }
+
+    /// Returns the patch of a modified file, if the changes file has one along with the hashes
+    /// needed to check it.
+    pub fn delta(&self, path: &str) -> Option<Delta> {
+        let info = self.files.get(path)?;
+        Some(Delta {
+            path: info.delta.clone()?,
+            source_sha1: info.old_sha1.clone()?,
+            result_sha1: info.new_sha1.clone()?,
+        })
+    }
+
+    /// Combines the changes of consecutive updates into the changes from the initial build of the
+    /// first update to the final build of the last one. A patch is only kept if no other update
+    /// changed its file, as it only applies to the build right before its update.
+    pub fn combine(updates: &[Changes]) -> Changes {
+        // Whether each file was in the initial build and is in the final build.
+        let mut states: BTreeMap<String, (bool, bool, FileInfo)> = BTreeMap::new();
+        for changes in updates {
+            let info = |path: &String| changes.files.get(path).cloned().unwrap_or_default();
+            for path in &changes.added {
+                let state = states.entry(path.clone()).or_insert((false, false, FileInfo::default()));
+                state.1 = true;
+                state.2.new_sha1 = info(path).new_sha1;
+                state.2.new_size = info(path).new_size;
+                state.2.delta = None;
             }
+            let modified = changes.modified.iter().map(|path| (path, true));
+            let removed = changes.removed.iter().map(|path| (path, false));
+            for (path, exists) in modified.chain(removed) {
+                let info = info(path);
+                let first = !states.contains_key(path);
+                let state = states.entry(path.clone()).or_insert((true, false, FileInfo {
+                    old_sha1: info.old_sha1,
+                    old_size: info.old_size,
+                    ..FileInfo::default()
+                }));
+                state.1 = exists;
+                state.2.new_sha1 = info.new_sha1;
+                state.2.new_size = info.new_size;
+                // The patch applies to the initial build only if this update is the first to change the file.
+                state.2.delta = if first && exists { info.delta } else { None };
+            }
+        }
+
+        let last = updates.last().cloned().unwrap_or_default();
+        let mut combined = Changes {
+            initial_build: updates.first().map(|changes| changes.initial_build.clone()).unwrap_or_default(),
+            added: vec![],
+            removed: vec![],
+            modified: vec![],
+            files: BTreeMap::new(),
+            ..last
         };
-        match serde_json::from_str::<Changes>(&changes) {
-            Ok(changes) => Some(changes),
-            Err(error) => {
-                eprintln!("Error parsing changes file: {}", error);
-                None
+        for (path, (in_initial, in_final, info)) in states {
+            match (in_initial, in_final) {
+                (true, true) => combined.modified.push(path.clone()),
+                (true, false) => combined.removed.push(path.clone()),
+                (false, true) => combined.added.push(path.clone()),
+                (false, false) => continue,
+            }
+            combined.files.insert(path, info);
+        }
+        combined
+    }
+
+    /// Returns the changes that undo these, from the final build back to the initial build. Added
+    /// and removed files swap, as do the old and new hashes. Patches are left out, as they only
+    /// turn the old files into the new ones.
+    pub fn reverse(&self) -> Changes {
+        Changes {
+            initial_build: self.final_build.clone(),
+            final_build: self.initial_build.clone(),
+            added: self.removed.clone(),
+            removed: self.added.clone(),
+            modified: self.modified.clone(),
+            manifest: String::new(),
+            files: self.files.iter().map(|(path, info)| (path.clone(), FileInfo {
+                old_sha1: info.new_sha1.clone(),
+                old_size: info.new_size,
+                new_sha1: info.old_sha1.clone(),
+                new_size: info.old_size,
+                delta: None,
+            })).collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Returns what the changes tell about the initial and the final build: the files modified or
+    /// removed are in the initial build with their old hashes, the files added or modified are in
+    /// the final build with their new hashes, and the rest are absent.
+    pub fn fingerprints(&self) -> (Fingerprint, Fingerprint) {
+        let paths = |paths: &[String]| paths.iter()
+            .filter(|path| !path.contains(".RedAlt-Steam-Installer"))
+            .cloned()
+            .collect::<Vec<String>>();
+        let mut initial = Fingerprint { build: self.initial_build.clone(), ..Fingerprint::default() };
+        for path in paths(&self.modified).into_iter().chain(paths(&self.removed)) {
+            if let Some(info) = self.files.get(&path).filter(|info| info.old_sha1.is_some()) {
+                initial.files.insert(path, (info.old_sha1.clone().unwrap(), info.old_size));
+            }
+        }
+        initial.absent.extend(paths(&self.added));
+
+        let mut last = Fingerprint { build: self.final_build.clone(), ..Fingerprint::default() };
+        for path in paths(&self.added).into_iter().chain(paths(&self.modified)) {
+            if let Some(info) = self.files.get(&path).filter(|info| info.new_sha1.is_some()) {
+                last.files.insert(path, (info.new_sha1.clone().unwrap(), info.new_size));
             }
         }
+        last.absent.extend(paths(&self.removed));
+        (initial, last)
+    }
+
+    /// Returns the hash of the file at the initial build, if the changes file has it.
+    pub fn old_hash(&self, path: &str) -> Option<String> {
+        self.files.get(path)?.old_sha1.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(initial_build: &str, final_build: &str, added: &[&str], removed: &[&str], modified: &[&str]) -> Changes {
+        let paths = |paths: &[&str]| paths.iter().map(|path| path.to_string()).collect();
+        let mut changes = Changes {
+            initial_build: initial_build.to_string(),
+            final_build: final_build.to_string(),
+            added: paths(added),
+            removed: paths(removed),
+            modified: paths(modified),
+            ..Changes::default()
+        };
+        // Each version of a file is named after the build that has it.
+        for path in added.iter().chain(removed).chain(modified) {
+            let exists_before = !added.contains(path);
+            let exists_after = !removed.contains(path);
+            changes.files.insert(path.to_string(), FileInfo {
+                old_sha1: exists_before.then(|| format!("{}@{}", path, initial_build)),
+                new_sha1: exists_after.then(|| format!("{}@{}", path, final_build)),
+                ..FileInfo::default()
+            });
+        }
+        changes
+    }
+
+    fn with_delta(mut changes: Changes, path: &str) -> Changes {
+        changes.files.get_mut(path).unwrap().delta = Some(format!(".RedAlt-Steam-Installer/deltas/{}.delta", path));
+        changes
+    }
+
+    #[test]
+    fn added_then_removed_is_left_out() {
+        let combined = Changes::combine(&[update("1", "2", &["a"], &[], &[]), update("2", "3", &[], &["a"], &[])]);
+        assert!(combined.added.is_empty() && combined.removed.is_empty() && combined.modified.is_empty());
+        assert!(combined.files.is_empty());
+    }
+
+    #[test]
+    fn modified_then_removed_is_removed() {
+        let combined = Changes::combine(&[update("1", "2", &[], &[], &["a"]), update("2", "3", &[], &["a"], &[])]);
+        assert_eq!(combined.removed, ["a"]);
+        assert!(combined.added.is_empty() && combined.modified.is_empty());
+        assert_eq!(combined.files["a"].old_sha1.as_deref(), Some("a@1"));
+        assert_eq!(combined.files["a"].new_sha1, None);
+    }
+
+    #[test]
+    fn removed_then_added_is_modified() {
+        let combined = Changes::combine(&[update("1", "2", &[], &["a"], &[]), update("2", "3", &["a"], &[], &[])]);
+        assert_eq!(combined.modified, ["a"]);
+        assert_eq!(combined.files["a"].old_sha1.as_deref(), Some("a@1"));
+        assert_eq!(combined.files["a"].new_sha1.as_deref(), Some("a@3"));
+    }
+
+    #[test]
+    fn added_then_modified_is_added() {
+        let combined = Changes::combine(&[update("1", "2", &["a"], &[], &[]), update("2", "3", &[], &[], &["a"])]);
+        assert_eq!(combined.added, ["a"]);
+        assert_eq!(combined.files["a"].old_sha1, None);
+        assert_eq!(combined.files["a"].new_sha1.as_deref(), Some("a@3"));
+    }
+
+    #[test]
+    fn builds_span_the_updates() {
+        let combined = Changes::combine(&[update("1", "2", &["a"], &[], &[]), update("2", "3", &["b"], &[], &[])]);
+        assert_eq!((combined.initial_build.as_str(), combined.final_build.as_str()), ("1", "3"));
+        assert_eq!(combined.added, ["a", "b"]);
+    }
+
+    #[test]
+    fn patches_are_kept_only_for_files_one_update_changed() {
+        let first = with_delta(with_delta(update("1", "2", &[], &[], &["a", "b"]), "a"), "b");
+        let second = with_delta(update("2", "3", &[], &[], &["b"]), "b");
+        let combined = Changes::combine(&[first, second]);
+        assert_eq!(combined.delta("a").map(|delta| (delta.source_sha1, delta.result_sha1)),
+                   Some(("a@1".to_string(), "a@2".to_string())));
+        assert!(combined.files["b"].delta.is_none());
+    }
+
+    #[test]
+    fn parses_version_1() {
+        let changes: Changes = serde_json::from_str(r#"{
+            "name": "Game", "app": "1", "depot": "2", "initial_build": "100", "final_build": "200",
+            "added": ["new.txt"], "removed": ["old.txt"], "modified": ["data/a b.pak"], "manifest": "manifest.sha1"
+        }"#).unwrap();
+        assert_eq!((changes.name.as_str(), changes.app.as_str(), changes.depot.as_str()), ("Game", "1", "2"));
+        assert_eq!((changes.initial_build.as_str(), changes.final_build.as_str()), ("100", "200"));
+        assert_eq!(changes.added, ["new.txt"]);
+        assert_eq!(changes.removed, ["old.txt"]);
+        assert_eq!(changes.modified, ["data/a b.pak"]);
+        assert_eq!(changes.manifest, "manifest.sha1");
+        assert!(changes.files.is_empty());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn version_2_round_trip() {
+        let changes = with_delta(update("100", "200", &["new.txt"], &["old.txt"], &["a.pak"]), "a.pak");
+        let json = serde_json::to_string(&changes).unwrap();
+        assert!(json.contains(&format!("\"version\":{}", VERSION)));
+        let parsed: Changes = serde_json::from_str(&json).unwrap();
+        assert_eq!((parsed.initial_build.as_str(), parsed.final_build.as_str()), ("100", "200"));
+        assert_eq!((&parsed.added, &parsed.removed, &parsed.modified), (&changes.added, &changes.removed, &changes.modified));
+        assert!(parsed.files == changes.files, "the hashes, sizes or patches changed");
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let error = serde_json::from_str::<Changes>(&format!(r#"{{
+            "version": {}, "added": [], "removed": [], "modified": []
+        }}"#, VERSION + 1)).err().unwrap();
+        assert!(error.to_string().contains("Unsupported changes file version"), "{}", error);
+    }
+}
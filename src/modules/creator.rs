@@ -0,0 +1,293 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{create_dir_all, metadata, File};
+use std::io::Read;
+use std::path::Path;
+use crate::modules::archive::{find_update_path, list_entries, read_entries};
+use crate::modules::backup::{Backup, BackupFormat};
+use crate::modules::chain::Package;
+use crate::modules::changes::{Changes, FileInfo};
+use crate::modules::delta::create_patch;
+use crate::modules::error::{Error, Result};
+use crate::modules::files::{copy_and_hash, hash_file, list_files};
+use crate::modules::output;
+
+/// Name of the folder in an update that holds the installer and the changes file.
+pub const INSTALLER_DIRECTORY: &str = ".RedAlt-Steam-Installer";
+
+/// Compares two game directories and returns the changes needed to go from the old to the new one.
+///
+/// The metadata (name, app, depot and builds) is copied from `details`.
+pub fn compare_directories(old_directory: &Path, new_directory: &Path, details: &Changes) -> Result<Changes> {
+    let old_files = list_files(old_directory).map_err(Error::io(old_directory))?;
+    let new_files = list_files(new_directory).map_err(Error::io(new_directory))?;
+
+    let mut changes = Changes {
+        added: vec![],
+        removed: vec![],
+        modified: vec![],
+        files: BTreeMap::new(),
+        ..details.clone()
+    };
+    // Every changed file is hashed, so the installer can check the game before and after updating.
+    let hash_and_size = |file: &Path| -> Result<(String, u64)> {
+        let size = metadata(file).map_err(Error::io(file))?.len();
+        Ok((hash_file(file).map_err(Error::io(file))?, size))
+    };
+    for path in &new_files {
+        let (new_sha1, new_size) = hash_and_size(&new_directory.join(path))?;
+        if old_files.binary_search(path).is_err() {
+            changes.added.push(path.clone());
+            changes.files.insert(path.clone(), FileInfo {
+                new_sha1: Some(new_sha1),
+                new_size: Some(new_size),
+                ..FileInfo::default()
+            });
+            continue;
+        }
+
+        let (old_sha1, old_size) = hash_and_size(&old_directory.join(path))?;
+        if old_size != new_size || old_sha1 != new_sha1 {
+            output::message(&format!("Comparing {}...\tModified.", path));
+            changes.modified.push(path.clone());
+            changes.files.insert(path.clone(), FileInfo {
+                old_sha1: Some(old_sha1),
+                old_size: Some(old_size),
+                new_sha1: Some(new_sha1),
+                new_size: Some(new_size),
+                delta: None,
+            });
+        } else {
+            output::message(&format!("Comparing {}...\tUnchanged.", path));
+        }
+    }
+    changes.removed = old_files.into_iter()
+        .filter(|path| new_files.binary_search(path).is_err())
+        .collect();
+    for path in &changes.removed {
+        let (old_sha1, old_size) = hash_and_size(&old_directory.join(path))?;
+        changes.files.insert(path.clone(), FileInfo {
+            old_sha1: Some(old_sha1),
+            old_size: Some(old_size),
+            ..FileInfo::default()
+        });
+    }
+    Ok(changes)
+}
+
+/// Creates an update in the output directory containing the added and modified files and the
+/// changes file (in the installer folder, where the installer looks for it).
+///
+/// With `deltas`, modified files are stored as binary patches against the old files instead,
+/// unless the patch would not be smaller than the new file.
+pub fn create_update(old_directory: &Path, new_directory: &Path, output_directory: &Path, details: &Changes, deltas: bool) -> Result<()> {
+    output::message(&format!("Creating update from {} to {}", old_directory.display(), new_directory.display()));
+    let mut changes = compare_directories(old_directory, new_directory, details)?;
+
+    let mut errors = vec![];
+    let mut patched = vec![];
+    for path in changes.modified.iter().filter(|_| deltas) {
+        let delta = format!("{}/deltas/{}.delta", INSTALLER_DIRECTORY, path);
+        let patch_file = output_directory.join(&delta);
+        output::message(&format!("Creating a patch for {}", path));
+        let size = create_dir_all(patch_file.parent().unwrap())
+            .and_then(|_| create_patch(&old_directory.join(path), &new_directory.join(path), &patch_file));
+        match size {
+            Ok(size) if size < changes.files[path].new_size.unwrap_or_default() => {
+                changes.files.get_mut(path).unwrap().delta = Some(delta);
+                patched.push(path.clone());
+            },
+            Ok(_) => {
+                let _ = std::fs::remove_file(&patch_file);
+            },
+            Err(error) => errors.push(Error::io(&patch_file)(error)),
+        }
+    }
+
+    for path in changes.added.iter().chain(&changes.modified).filter(|path| !patched.contains(path)) {
+        output::message(&format!("Copying {} to {}", path, output_directory.display()));
+        let output_file = output_directory.join(path);
+        let copy = create_dir_all(output_file.parent().unwrap())
+            .and_then(|_| std::fs::copy(new_directory.join(path), &output_file));
+        if let Err(error) = copy {
+            errors.push(Error::io(&output_file)(error));
+        }
+    }
+
+    let changes_file = output_directory.join(INSTALLER_DIRECTORY).join("changes.json");
+    create_dir_all(changes_file.parent().unwrap()).map_err(Error::io(changes_file.parent().unwrap()))?;
+    changes.write_changes(&changes_file)?;
+
+    output::message(&format!("{} added, {} removed, {} modified ({} as patches). Wrote changes to {}.",
+                             changes.added.len(), changes.removed.len(), changes.modified.len(), patched.len(), changes_file.display()));
+    Error::collect(errors)
+}
+
+/// Merges consecutive updates into one cumulative update in the output directory. It holds the
+/// latest version of every file the updates add or modify and still exists at the end, and the
+/// combined changes file. A patch is kept if no other update changed its file. Nothing is written
+/// if an update is missing a file, or only has a patch for a file that other updates changed too.
+pub fn merge_updates(packages: &[Package], output_directory: &Path) -> Result<Changes> {
+    let updates: Vec<Changes> = packages.iter().map(|package| package.changes.clone()).collect();
+    let mut changes = Changes::combine(&updates);
+    output::message(&format!("Merging {} updates from build {} to build {}", packages.len(), changes.initial_build, changes.final_build));
+
+    // The latest version of each file is in the last update that added or modified it.
+    let mut latest: BTreeMap<String, usize> = BTreeMap::new();
+    for (index, package) in packages.iter().enumerate() {
+        for path in package.changes.added.iter().chain(&package.changes.modified) {
+            latest.insert(path.clone(), index);
+        }
+    }
+
+    // Find the files and patches to copy from each update first.
+    let mut copies: Vec<Vec<String>> = vec![];
+    let mut errors = vec![];
+    for (index, package) in packages.iter().enumerate() {
+        let paths: Vec<String> = changes.added.iter().chain(&changes.modified)
+            .filter(|path| latest.get(*path) == Some(&index) && !path.contains(INSTALLER_DIRECTORY))
+            .cloned()
+            .collect();
+        let deltas: Vec<Option<String>> = paths.iter()
+            .map(|path| changes.files.get(path).and_then(|info| info.delta.clone()))
+            .collect();
+        let wanted: Vec<String> = paths.iter().cloned().chain(deltas.iter().flatten().cloned()).collect();
+        let available: HashSet<&String> = match (&package.update_archive, &package.update_directory) {
+            (Some(archive), _) => list_entries(archive).map_err(Error::io(archive))?.iter()
+                .filter_map(|(name, _)| find_update_path(name, &wanted))
+                .collect(),
+            (None, Some(update_directory)) => wanted.iter().filter(|path| update_directory.join(path).is_file()).collect(),
+            _ => HashSet::new(),
+        };
+
+        let mut package_copies = vec![];
+        for (path, delta) in paths.iter().zip(deltas) {
+            let delta = delta.filter(|delta| available.contains(delta));
+            if delta.is_none() {
+                // The merged update can only use the patches it has.
+                if let Some(info) = changes.files.get_mut(path) {
+                    info.delta = None;
+                }
+            }
+            match (available.contains(path), delta) {
+                (true, delta) => package_copies.extend([Some(path.clone()), delta].into_iter().flatten()),
+                (false, Some(delta)) => package_copies.push(delta),
+                (false, None) => errors.push(match package.changes.delta(path) {
+                    Some(_) => Error::MissingSource(format!("{} (only a patch is in {}, and other updates change the file)",
+                                                            path, package.location().display())),
+                    None => Error::MissingSource(format!("{} (in {})", path, package.location().display())),
+                }),
+            }
+        }
+        copies.push(package_copies);
+    }
+    Error::collect(errors)?;
+
+    let mut errors = vec![];
+    for (package, paths) in packages.iter().zip(&copies) {
+        let mut copied = vec![false; paths.len()];
+        let mut copy = |path: &str, new_file: &mut dyn Read| {
+            output::message(&format!("Copying {} from {}", path, package.location().display()));
+            errors.extend(copy_new_file(path, new_file, output_directory, &changes).err());
+        };
+        match (&package.update_archive, &package.update_directory) {
+            (Some(archive), _) => read_entries(archive, |name, entry| {
+                if let Some(path) = find_update_path(name, paths) {
+                    let index = paths.iter().position(|new_file| new_file == path).unwrap();
+                    if !copied[index] {
+                        copied[index] = true;
+                        copy(path, entry);
+                    }
+                }
+            }).map_err(Error::io(archive))?,
+            (None, Some(update_directory)) => for (index, path) in paths.iter().enumerate() {
+                let new_file = update_directory.join(path);
+                if let Ok(mut new_file) = File::open(&new_file) {
+                    copied[index] = true;
+                    copy(path, &mut new_file);
+                }
+            },
+            _ => {},
+        }
+        for (path, copied) in paths.iter().zip(copied) {
+            if !copied {
+                errors.push(Error::MissingSource(format!("{} (in {})", path, package.location().display())));
+            }
+        }
+    }
+
+    let changes_file = output_directory.join(INSTALLER_DIRECTORY).join("changes.json");
+    create_dir_all(changes_file.parent().unwrap()).map_err(Error::io(changes_file.parent().unwrap()))?;
+    changes.write_changes(&changes_file)?;
+    output::message(&format!("{} added, {} removed, {} modified. Wrote changes to {}.",
+                             changes.added.len(), changes.removed.len(), changes.modified.len(), changes_file.display()));
+    Error::collect(errors).map(|_| changes)
+}
+
+/// Creates an update in the output directory that reverts the update of the backup, for
+/// downgrading games at its final build. The files the update modified or removed are copied from
+/// the backup, and the changes file has the builds swapped and the added and removed files
+/// swapped.
+pub fn create_reverse(game_directory: &Path, backup: &Backup, output_directory: &Path) -> Result<Changes> {
+    let mut changes = backup.changes(game_directory)?.reverse();
+    // Older changes files have no hashes, but the backup does.
+    for file in &backup.files {
+        let info = changes.files.entry(file.path.clone()).or_default();
+        info.new_sha1.get_or_insert_with(|| file.sha1.clone());
+        info.new_size.get_or_insert(file.size);
+    }
+    output::message(&format!("Creating a downgrade from build {} to build {} with the backup {}",
+                             changes.initial_build, changes.final_build, backup.id));
+
+    let paths: Vec<String> = changes.added.iter().chain(&changes.modified)
+        .filter(|path| !path.contains(INSTALLER_DIRECTORY))
+        .cloned()
+        .collect();
+    let mut copied = vec![false; paths.len()];
+    let mut errors = vec![];
+    let mut copy = |path: &str, old_file: &mut dyn Read| {
+        output::message(&format!("Copying {} to {}", path, output_directory.display()));
+        errors.extend(copy_new_file(path, old_file, output_directory, &changes).err());
+    };
+    match backup.format {
+        BackupFormat::Zip | BackupFormat::TarZst => {
+            let archive = backup.location(game_directory);
+            read_entries(&archive, |name, entry| {
+                if let Some(index) = paths.iter().position(|path| path == name) {
+                    copied[index] = true;
+                    copy(name, entry);
+                }
+            }).map_err(Error::io(&archive))?;
+        },
+        _ => for (index, path) in paths.iter().enumerate() {
+            if let Some(mut old_file) = backup.backup_file(game_directory, path).and_then(|file| File::open(file).ok()) {
+                copied[index] = true;
+                copy(path, &mut old_file);
+            }
+        },
+    }
+    for (path, copied) in paths.iter().zip(copied) {
+        if !copied {
+            errors.push(Error::MissingSource(format!("{} (not in the backup {})", path, backup.id)));
+        }
+    }
+
+    let changes_file = output_directory.join(INSTALLER_DIRECTORY).join("changes.json");
+    create_dir_all(changes_file.parent().unwrap()).map_err(Error::io(changes_file.parent().unwrap()))?;
+    changes.write_changes(&changes_file)?;
+    output::message(&format!("{} added, {} removed, {} modified. Wrote changes to {}.",
+                             changes.added.len(), changes.removed.len(), changes.modified.len(), changes_file.display()));
+    Error::collect(errors).map(|_| changes)
+}
+
+/// Copies a file of the update to the output directory, checking it against the new hash in the
+/// changes.
+fn copy_new_file(path: &str, new_file: &mut dyn Read, output_directory: &Path, changes: &Changes) -> Result<()> {
+    let output_file = output_directory.join(path);
+    let hash = create_dir_all(output_file.parent().unwrap())
+        .and_then(|_| copy_and_hash(new_file, &mut File::create(&output_file)?))
+        .map_err(Error::io(&output_file))?;
+    match changes.files.get(path).and_then(|info| info.new_sha1.as_ref()) {
+        Some(expected) if !hash.eq_ignore_ascii_case(expected) => Err(Error::HashMismatch(path.to_string())),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+/// First bytes of every patch file.
+const MAGIC: &[u8; 8] = b"RADELTA1";
+
+const COPY: u8 = 0;
+const INSERT: u8 = 1;
+const END: u8 = 2;
+
+/// Smallest block of the old file that is matched in the new file.
+const MIN_BLOCK_SIZE: u64 = 4096;
+/// Upper limit for the number of blocks indexed per old file, so huge files keep a small index.
+const MAX_BLOCKS: u64 = 1 << 20;
+
+/// Binary patch that turns one version of a game file into another.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Delta {
+    /// Path of the patch file in the update.
+    pub path: String,
+    /// SHA-1 of the game file the patch applies to.
+    pub source_sha1: String,
+    /// SHA-1 of the file the patch produces.
+    pub result_sha1: String,
+}
+
+/// Writes a patch that rebuilds the new file from the old file, and returns its size.
+///
+/// The patch is a list of instructions that either copy a range of the old file or insert new
+/// bytes. Blocks of the old file are found in the new file with a rolling checksum, so moved and
+/// shifted data is copied rather than inserted.
+pub fn create_patch(old_file: &Path, new_file: &Path, patch_file: &Path) -> std::io::Result<u64> {
+    let old_size = old_file.metadata()?.len();
+    let block_size = (old_size / MAX_BLOCKS).max(MIN_BLOCK_SIZE) as usize;
+    let blocks = index_blocks(old_file, block_size)?;
+
+    let mut new = BufReader::new(File::open(new_file)?);
+    let mut patch = Writer { output: BufWriter::new(File::create(patch_file)?), copy: None, size: 0 };
+    patch.write(MAGIC)?;
+
+    // The window is `buffer[start..start + block_size]`, the bytes before it that did not match
+    // any block are waiting to be inserted from `literal`.
+    let mut buffer: Vec<u8> = vec![];
+    let (mut start, mut literal) = (0, 0);
+    let mut checksum = None;
+    loop {
+        if start + block_size > buffer.len() {
+            patch.insert(&buffer[literal..start])?;
+            buffer.drain(..start);
+            start = 0;
+            literal = 0;
+            let wanted = (block_size * 16).max(buffer.len() + block_size) - buffer.len();
+            let read = new.by_ref().take(wanted as u64).read_to_end(&mut buffer)?;
+            if start + block_size > buffer.len() {
+                if read == 0 {
+                    break;
+                }
+                continue;
+            }
+        }
+
+        let window = &buffer[start..start + block_size];
+        let current = *checksum.get_or_insert_with(|| Checksum::new(window));
+        let matched = match blocks.get(&current.value()) {
+            Some(candidates) => find_block(candidates, window),
+            None => None,
+        };
+        match matched {
+            Some(offset) => {
+                patch.insert(&buffer[literal..start])?;
+                patch.copy(offset, block_size as u64)?;
+                start += block_size;
+                literal = start;
+                checksum = None;
+            },
+            None if start + block_size < buffer.len() => {
+                checksum = Some(current.roll(buffer[start], buffer[start + block_size], block_size));
+                start += 1;
+            },
+            None => {
+                // The next byte has not been read yet.
+                start += 1;
+                checksum = None;
+            },
+        }
+    }
+    patch.insert(&buffer[literal..])?;
+    patch.finish()
+}
+
+/// Rebuilds the new file from the old file and the patch, writing it to `output`, and returns
+/// the SHA-1 of the written file.
+pub fn apply_patch(old_file: &Path, patch: &mut dyn Read, output: &mut impl Write) -> std::io::Result<String> {
+    let mut magic = [0; 8];
+    patch.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_patch());
+    }
+
+    // Instructions are read a few bytes at a time.
+    let patch = &mut BufReader::new(patch);
+    let mut old = File::open(old_file)?;
+    let mut output = HashWriter { writer: output, hasher: Sha1::new() };
+    loop {
+        let mut instruction = [0; 1];
+        patch.read_exact(&mut instruction)?;
+        match instruction[0] {
+            COPY => {
+                let offset = read_u64(patch)?;
+                let length = read_u64(patch)?;
+                old.seek(SeekFrom::Start(offset))?;
+                if std::io::copy(&mut (&mut old).take(length), &mut output)? != length {
+                    return Err(invalid_patch());
+                }
+            },
+            INSERT => {
+                let length = read_u64(patch)?;
+                if std::io::copy(&mut (&mut *patch).take(length), &mut output)? != length {
+                    return Err(ErrorKind::UnexpectedEof.into());
+                }
+            },
+            END => break,
+            _ => return Err(invalid_patch()),
+        }
+    }
+    Ok(format!("{:x}", output.hasher.finalize()))
+}
+
+/// Offsets and SHA-1 of the blocks of the old file, by checksum.
+type Blocks = HashMap<u32, Vec<(u64, [u8; 20])>>;
+
+/// Indexes every whole block of the old file. The SHA-1 tells blocks with the same checksum apart
+/// without reading the old file again.
+fn index_blocks(old_file: &Path, block_size: usize) -> std::io::Result<Blocks> {
+    let mut blocks = Blocks::new();
+    let mut reader = BufReader::new(File::open(old_file)?);
+    let mut block = vec![0; block_size];
+    let mut offset = 0;
+    loop {
+        match reader.read_exact(&mut block) {
+            Ok(()) => {},
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => break,
+            Err(error) => return Err(error),
+        }
+        blocks.entry(Checksum::new(&block).value()).or_default().push((offset, Sha1::digest(&block).into()));
+        offset += block_size as u64;
+    }
+    Ok(blocks)
+}
+
+/// Returns the offset of a block of the old file with the same bytes as the window.
+fn find_block(candidates: &[(u64, [u8; 20])], window: &[u8]) -> Option<u64> {
+    let hash: [u8; 20] = Sha1::digest(window).into();
+    candidates.iter().find(|(_, block_hash)| *block_hash == hash).map(|(offset, _)| *offset)
+}
+
+fn read_u64(reader: &mut dyn Read) -> std::io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn invalid_patch() -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, "Invalid patch file")
+}
+
+/// Adler-32 style checksum that can be moved along the data one byte at a time.
+#[derive(Clone, Copy)]
+struct Checksum {
+    a: u32,
+    b: u32,
+}
+
+impl Checksum {
+    fn new(block: &[u8]) -> Checksum {
+        let (mut a, mut b) = (0u32, 0u32);
+        for byte in block {
+            a = a.wrapping_add(*byte as u32);
+            b = b.wrapping_add(a);
+        }
+        Checksum { a, b }
+    }
+
+    fn roll(self, removed: u8, added: u8, block_size: usize) -> Checksum {
+        let a = self.a.wrapping_sub(removed as u32).wrapping_add(added as u32);
+        let b = self.b.wrapping_sub((block_size as u32).wrapping_mul(removed as u32)).wrapping_add(a);
+        Checksum { a, b }
+    }
+
+    fn value(self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+/// Writes the instructions of a patch, merging copies of consecutive ranges of the old file.
+struct Writer<W: Write> {
+    output: W,
+    copy: Option<(u64, u64)>,
+    size: u64,
+}
+
+impl<W: Write> Writer<W> {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.size += bytes.len() as u64;
+        self.output.write_all(bytes)
+    }
+
+    fn copy(&mut self, offset: u64, length: u64) -> std::io::Result<()> {
+        match &mut self.copy {
+            Some((start, copied)) if *start + *copied == offset => *copied += length,
+            _ => {
+                self.flush_copy()?;
+                self.copy = Some((offset, length));
+            },
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        self.flush_copy()?;
+        self.write(&[INSERT])?;
+        self.write(&(bytes.len() as u64).to_le_bytes())?;
+        self.write(bytes)
+    }
+
+    fn flush_copy(&mut self) -> std::io::Result<()> {
+        if let Some((offset, length)) = self.copy.take() {
+            self.write(&[COPY])?;
+            self.write(&offset.to_le_bytes())?;
+            self.write(&length.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> std::io::Result<u64> {
+        self.flush_copy()?;
+        self.write(&[END])?;
+        self.output.flush()?;
+        Ok(self.size)
+    }
+}
+
+/// Hashes everything written through it.
+struct HashWriter<'a, W: Write> {
+    writer: &'a mut W,
+    hasher: Sha1,
+}
+
+impl<W: Write> Write for HashWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes that do not repeat, so blocks only match where the data was really kept.
+    fn random_bytes(length: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed.max(1);
+        (0..length).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        }).collect()
+    }
+
+    /// Creates a patch from the old to the new bytes, checks that applying it gives the new bytes
+    /// and their hash, and returns the size of the patch.
+    fn round_trip(name: &str, old: &[u8], new: &[u8]) -> u64 {
+        let directory = std::env::temp_dir().join(format!("redalt-delta-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&directory).unwrap();
+        let (old_file, new_file, patch_file) = (directory.join("old"), directory.join("new"), directory.join("patch"));
+        std::fs::write(&old_file, old).unwrap();
+        std::fs::write(&new_file, new).unwrap();
+
+        let size = create_patch(&old_file, &new_file, &patch_file).unwrap();
+        let mut output = vec![];
+        let hash = apply_patch(&old_file, &mut File::open(&patch_file).unwrap(), &mut output).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(output == new, "the patched file differs from the new file");
+        assert_eq!(hash, format!("{:x}", Sha1::digest(new)));
+        size
+    }
+
+    #[test]
+    fn empty_old_file() {
+        let new = random_bytes(10_000, 1);
+        assert!(round_trip("empty-old", &[], &new) > new.len() as u64);
+    }
+
+    #[test]
+    fn empty_new_file() {
+        round_trip("empty-new", &random_bytes(10_000, 2), &[]);
+    }
+
+    #[test]
+    fn identical_files() {
+        let old = random_bytes(100_000, 3);
+        // Only the end of the file, which is shorter than a block, is inserted.
+        assert!(round_trip("identical", &old, &old) < MIN_BLOCK_SIZE + 100);
+    }
+
+    #[test]
+    fn inserts() {
+        let old = random_bytes(100_000, 4);
+        let mut new = old.clone();
+        new.splice(50_000..50_000, random_bytes(1_000, 5));
+        new.splice(0..0, random_bytes(10, 6));
+        new.extend(random_bytes(300, 7));
+        assert!(round_trip("inserts", &old, &new) < 20_000);
+    }
+
+    #[test]
+    fn deletes() {
+        let old = random_bytes(100_000, 8);
+        let mut new = old.clone();
+        new.drain(70_000..75_000);
+        new.drain(10_000..10_100);
+        new.truncate(90_000);
+        assert!(round_trip("deletes", &old, &new) < 20_000);
+    }
+
+    #[test]
+    fn moved_blocks() {
+        let old = random_bytes(100_000, 9);
+        let new = [&old[60_000..], &old[20_000..60_000], &old[..20_000]].concat();
+        assert!(round_trip("moved", &old, &new) < 20_000);
+    }
+
+    #[test]
+    fn files_smaller_than_a_block() {
+        round_trip("small", b"old contents", b"new contents");
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::metadata;
+use std::io::ErrorKind;
+use std::path::Path;
+use crate::modules::error::{Error, Result};
+use crate::modules::files::hash_file;
+use crate::modules::manifest::Manifest;
+
+/// What is known about the files of a build, used to recognise the build of a game directory.
+#[derive(Clone, Default)]
+pub struct Fingerprint {
+    pub build: String,
+    /// Files of the build by path, with their SHA-1 and size (if known).
+    pub files: BTreeMap<String, (String, Option<u64>)>,
+    /// Files that are not part of the build.
+    pub absent: BTreeSet<String>,
+}
+
+impl Fingerprint {
+    /// Takes every file of the manifest as a file of the build.
+    pub fn from_manifest(build: &str, manifest: &Manifest) -> Fingerprint {
+        Fingerprint {
+            build: build.to_string(),
+            files: manifest.files().iter()
+                .map(|file| (file.name.replace('\\', "/"), (file.hash.to_lowercase(), file.size)))
+                .collect(),
+            absent: BTreeSet::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.absent.is_empty()
+    }
+
+    /// Adds what another fingerprint of the same build knows.
+    pub fn extend(&mut self, other: Fingerprint) {
+        self.files.extend(other.files);
+        self.absent.extend(other.absent);
+    }
+}
+
+/// How well the game directory matches a build.
+pub struct Comparison {
+    pub build: String,
+    pub matched: usize,
+    /// Files with a different size or hash.
+    pub mismatched: Vec<String>,
+    pub missing: Vec<String>,
+    /// Files that are not part of the build but are in the game directory.
+    pub unexpected: Vec<String>,
+}
+
+impl Comparison {
+    pub fn differences(&self) -> usize {
+        self.mismatched.len() + self.missing.len() + self.unexpected.len()
+    }
+}
+
+/// Sizes and hashes of the game files, each read at most once.
+struct GameFiles<'a> {
+    directory: &'a Path,
+    sizes: HashMap<String, Option<u64>>,
+    hashes: HashMap<String, String>,
+}
+
+impl GameFiles<'_> {
+    fn size(&mut self, path: &str) -> Result<Option<u64>> {
+        if let Some(size) = self.sizes.get(path) {
+            return Ok(*size);
+        }
+        let file = self.directory.join(path);
+        let size = match metadata(&file) {
+            Ok(metadata) => Some(metadata.len()),
+            Err(error) if error.kind() == ErrorKind::NotFound => None,
+            Err(error) => return Err(Error::io(&file)(error)),
+        };
+        self.sizes.insert(path.to_string(), size);
+        Ok(size)
+    }
+
+    fn hash(&mut self, path: &str) -> Result<&str> {
+        if !self.hashes.contains_key(path) {
+            let file = self.directory.join(path);
+            self.hashes.insert(path.to_string(), hash_file(&file).map_err(Error::io(&file))?);
+        }
+        Ok(&self.hashes[path])
+    }
+
+    /// Whether the file has the size (if known) and the hash. Files of the wrong size are not hashed.
+    fn matches(&mut self, path: &str, hash: &str, size: Option<u64>) -> Result<bool> {
+        match (self.size(path)?, size) {
+            (None, _) => Ok(false),
+            (Some(actual), Some(expected)) if actual != expected => Ok(false),
+            _ => Ok(self.hash(path)?.eq_ignore_ascii_case(hash)),
+        }
+    }
+}
+
+/// Returns the builds whose fingerprint matches the game directory exactly. Sizes are compared
+/// first, so only the files of builds that are still possible are hashed.
+pub fn matching_builds(game_directory: &Path, fingerprints: &[Fingerprint]) -> Result<Vec<String>> {
+    let mut game_files = GameFiles { directory: game_directory, sizes: HashMap::new(), hashes: HashMap::new() };
+    let mut candidates = vec![];
+    for fingerprint in fingerprints.iter().filter(|fingerprint| !fingerprint.is_empty()) {
+        let mut matches = true;
+        for path in &fingerprint.absent {
+            matches &= game_files.size(path)?.is_none();
+        }
+        for (path, (_, size)) in &fingerprint.files {
+            matches &= match (game_files.size(path)?, size) {
+                (None, _) => false,
+                (Some(actual), Some(expected)) => actual == *expected,
+                (Some(_), None) => true,
+            };
+        }
+        if matches {
+            candidates.push(fingerprint);
+        }
+    }
+
+    let mut builds = vec![];
+    for fingerprint in candidates {
+        let mut matches = true;
+        for (path, (hash, size)) in &fingerprint.files {
+            if !game_files.matches(path, hash, *size)? {
+                matches = false;
+                break;
+            }
+        }
+        if matches {
+            builds.push(fingerprint.build.clone());
+        }
+    }
+    Ok(builds)
+}
+
+/// Compares the game directory with every build, counting the files that match and differ.
+/// Files are only hashed if their size matches, and each file is hashed once.
+pub fn compare_builds(game_directory: &Path, fingerprints: &[Fingerprint]) -> Result<Vec<Comparison>> {
+    let mut game_files = GameFiles { directory: game_directory, sizes: HashMap::new(), hashes: HashMap::new() };
+    let mut comparisons = vec![];
+    for fingerprint in fingerprints {
+        let mut comparison = Comparison {
+            build: fingerprint.build.clone(),
+            matched: 0,
+            mismatched: vec![],
+            missing: vec![],
+            unexpected: vec![],
+        };
+        for (path, (hash, size)) in &fingerprint.files {
+            if game_files.size(path)?.is_none() {
+                comparison.missing.push(path.clone());
+            } else if game_files.matches(path, hash, *size)? {
+                comparison.matched += 1;
+            } else {
+                comparison.mismatched.push(path.clone());
+            }
+        }
+        for path in &fingerprint.absent {
+            if game_files.size(path)?.is_some() {
+                comparison.unexpected.push(path.clone());
+            }
+        }
+        comparisons.push(comparison);
+    }
+    Ok(comparisons)
+}
@@ -0,0 +1,140 @@
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A file or directory could not be read, written, copied or removed.
+    Io { path: PathBuf, source: std::io::Error },
+    /// A changes, manifest, settings or journal file is invalid.
+    Parse { path: PathBuf, message: String },
+    /// A file does not have the hash given in the manifest.
+    HashMismatch(String),
+    /// A file listed in the changes is not in the update.
+    MissingSource(String),
+    /// A game file does not match the file a patch was made from.
+    PatchSource(String),
+    /// Files did not match the manifest when validating a directory.
+    Validation { mismatched: Vec<String>, missing: Vec<String> },
+    /// A path needed by the command has not been set.
+    MissingPath(&'static str),
+    /// A path given for a setting does not exist or is of the wrong type.
+    InvalidPath { path: PathBuf, expected: &'static str },
+    /// A value given for a setting is invalid.
+    InvalidValue { field: String, value: String },
+    /// A setting with the given name does not exist.
+    UnknownField(String),
+    /// A command was given arguments it does not accept.
+    Usage(String),
+    /// Files that the update modifies or removes do not match the initial build.
+    WrongBuild { build: String, mismatched: Vec<String>, missing: Vec<String> },
+    /// Updates applied in a chain do not continue from each other's builds.
+    BrokenChain { final_build: String, initial_build: String },
+    /// No updates in the library lead from the installed build to the requested one.
+    NoUpdatePath { initial_build: String, final_build: String },
+    /// The installed build matches none or several of the known builds, which are listed.
+    UnknownBuild(Vec<String>),
+    /// No updates were found to apply.
+    NoUpdates,
+    /// A previous update of the game directory did not finish.
+    InterruptedUpdate(PathBuf),
+    /// The user did not confirm the operation.
+    Cancelled,
+    /// Some steps of an operation failed while the rest were completed.
+    Incomplete(Vec<Error>),
+}
+
+impl Error {
+    /// Returns a function that wraps an IO error with the path it happened on, for use with `map_err`.
+    pub fn io(path: &Path) -> impl FnOnce(std::io::Error) -> Error + '_ {
+        move |source| Error::Io { path: path.to_path_buf(), source }
+    }
+
+    /// Collects the errors of the steps of an operation, succeeding if there were none.
+    pub fn collect(errors: Vec<Error>) -> Result<()> {
+        let mut errors: Vec<Error> = errors.into_iter()
+            .flat_map(|error| match error {
+                Error::Incomplete(errors) => errors,
+                error => vec![error],
+            })
+            .collect();
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.pop().unwrap()),
+            _ => Err(Error::Incomplete(errors)),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Parse { path, message } => write!(f, "Error parsing {}: {}", path.display(), message),
+            Error::HashMismatch(path) => write!(f, "{}: Hash mismatch", path),
+            Error::MissingSource(path) => write!(f, "{}: Not found in the update", path),
+            Error::PatchSource(path) => write!(f, "{}: Does not match the file the patch was made from", path),
+            Error::Validation { mismatched, missing } => {
+                write!(f, "{} mismatches, {} missing.", mismatched.len(), missing.len())?;
+                write!(f, "\nBad files:")?;
+                for path in mismatched {
+                    write!(f, "\n  {} (hash mismatch)", path)?;
+                }
+                for path in missing {
+                    write!(f, "\n  {} (missing)", path)?;
+                }
+                Ok(())
+            },
+            Error::WrongBuild { build, mismatched, missing } => {
+                write!(f, "The game does not match the initial build{}.",
+                       if build.is_empty() { String::new() } else { format!(" ({})", build) })?;
+                write!(f, "\nConflicting files:")?;
+                for path in mismatched {
+                    write!(f, "\n  {} (hash mismatch)", path)?;
+                }
+                for path in missing {
+                    write!(f, "\n  {} (missing)", path)?;
+                }
+                Ok(())
+            },
+            Error::MissingPath(name) => write!(f, "Provide {}.", name),
+            Error::InvalidPath { path, expected } => write!(f, "{} is not {}", path.display(), expected),
+            Error::InvalidValue { field, value } => write!(f, "Invalid value \"{}\" for {}", value, field),
+            Error::UnknownField(field) => write!(f, "Field \"{}\" not found", field),
+            Error::Usage(message) => write!(f, "{}", message),
+            Error::BrokenChain { final_build, initial_build } => {
+                let build = |build: &str| if build.is_empty() { "an unknown build".to_string() } else { format!("build {}", build) };
+                write!(f, "The updates do not form a chain: one ends at {} but the next starts at {}.",
+                       build(final_build), build(initial_build))
+            },
+            Error::NoUpdatePath { initial_build, final_build } => {
+                write!(f, "No updates lead from build {} to build {}.", initial_build, final_build)
+            },
+            Error::UnknownBuild(builds) => match builds.as_slice() {
+                [] => write!(f, "The game does not match any build of the updates. Enter the installed build."),
+                builds => write!(f, "The game matches builds {}. Enter the installed build.", builds.join(", ")),
+            },
+            Error::NoUpdates => write!(f, "No updates were found."),
+            Error::InterruptedUpdate(path) => write!(f, "An interrupted update was found in {}. \
+            Enter \"resume\" to finish it or \"rollback\" to undo it.", path.display()),
+            Error::Cancelled => write!(f, "Cancelled."),
+            Error::Incomplete(errors) => {
+                write!(f, "{} errors:", errors.len())?;
+                for error in errors {
+                    write!(f, "\n  {}", error.to_string().replace('\n', "\n  "))?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::fs::{read_dir, File};
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+use sha1::{Digest, Sha1};
+
+/// Size of the buffer used when reading files, large enough to keep reads efficient on big game files.
+const BUFFER_SIZE: usize = 4 * 1024 * 1024;
+
+/// Folders created by the installer that are never part of the game files.
+const IGNORED_DIRECTORIES: [&str; 2] = [".Backup", ".RedAlt-Steam-Installer"];
+
+/// Lists every file below the directory as sorted paths relative to it, using "/" as separator.
+pub fn list_files(directory: &Path) -> std::io::Result<Vec<String>> {
+    let mut files = vec![];
+    let mut directories = vec![directory.to_path_buf()];
+    while let Some(current) = directories.pop() {
+        for entry in read_dir(&current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                let ignored = current == directory && IGNORED_DIRECTORIES.iter()
+                    .any(|ignored| path.file_name().is_some_and(|name| name == *ignored));
+                if !ignored {
+                    directories.push(path);
+                }
+            } else {
+                let relative = path.strip_prefix(directory).unwrap();
+                let relative = relative.components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                files.push(relative);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Returns the name of the file or folder for display. Paths without a name, like "." and "..",
+/// are resolved first, and shown as they are if that fails.
+pub fn display_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .or_else(|| std::fs::canonicalize(path).ok()?.file_name().map(|name| name.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    copy_and_hash(&mut file, &mut std::io::sink())
+}
+
+/// Copies the reader to the writer and returns the SHA-1 of the copied data.
+pub fn copy_and_hash(reader: &mut dyn Read, writer: &mut impl Write) -> std::io::Result<String> {
+    let mut hasher = Sha1::new();
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+        hasher.update(&buffer[..read]);
+        writer.write_all(&buffer[..read])?;
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Formats a number of bytes for display, e.g. "1.50 GiB".
+pub fn format_bytes(bytes: u64) -> String {
+    let units = ["bytes", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.2} {}", size, units[unit])
+    }
+}
@@ -0,0 +1,197 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::modules::delta::Delta;
+
+/// Name of the journal file in the game directory. It only exists while an update is in progress.
+pub const JOURNAL_FILE: &str = ".RedAlt-Journal.jsonl";
+
+#[derive(Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Copy,
+    Remove,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Operation {
+    pub action: Action,
+    pub path: String,
+    /// Whether the file was in the game directory before the update.
+    pub existed: bool,
+    /// Patch to apply to the game file instead of copying the whole new file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delta: Option<Delta>,
+    /// Index of the update in a chain that the operation belongs to.
+    #[serde(default, skip_serializing_if = "is_first")]
+    pub update: usize,
+    /// Whether an earlier update of the chain already changed the file. Only the first operation
+    /// on a file backs it up and rolls it back.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub repeated: bool,
+}
+
+fn is_first(update: &usize) -> bool {
+    *update == 0
+}
+
+#[derive(Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// The original file has been copied to the backup directory.
+    BackedUp,
+    /// The operation has been applied to the game directory.
+    Done,
+}
+
+/// First line of the journal, describing the planned update.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Plan {
+    pub update_directory: Option<PathBuf>,
+    pub update_archive: Option<PathBuf>,
+    pub backup_directory: Option<PathBuf>,
+    pub operations: Vec<Operation>,
+    /// Where the new files of the following updates come from, when several updates are applied
+    /// in a chain.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chain: Vec<UpdateSource>,
+}
+
+/// Folder or archive holding the new files of an update.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct UpdateSource {
+    pub update_directory: Option<PathBuf>,
+    pub update_archive: Option<PathBuf>,
+}
+
+impl Plan {
+    /// Number of updates applied by the plan.
+    pub fn updates(&self) -> usize {
+        1 + self.chain.len()
+    }
+
+    /// Returns where the new files of an update of the plan come from.
+    pub fn source(&self, update: usize) -> UpdateSource {
+        match update {
+            0 => UpdateSource {
+                update_directory: self.update_directory.clone(),
+                update_archive: self.update_archive.clone(),
+            },
+            update => self.chain[update - 1].clone(),
+        }
+    }
+}
+
+/// Every following line records the progress of a single operation.
+#[derive(Deserialize, Serialize)]
+struct Entry {
+    operation: usize,
+    stage: Stage,
+}
+
+/// Write-ahead journal of an update. The plan is written before the game directory is touched and
+/// every step is flushed to disk once it has been made, so an interrupted update can be resumed or
+/// rolled back exactly.
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    pub plan: Plan,
+    backed_up: Vec<bool>,
+    done: Vec<bool>,
+}
+
+impl Journal {
+    pub fn exists(game_directory: &Path) -> bool {
+        game_directory.join(JOURNAL_FILE).is_file()
+    }
+
+    pub fn create(game_directory: &Path, plan: Plan) -> std::io::Result<Journal> {
+        let path = game_directory.join(JOURNAL_FILE);
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+        writeln!(file, "{}", serde_json::to_string(&plan)?)?;
+        file.sync_all()?;
+        let operations = plan.operations.len();
+        Ok(Journal {
+            path,
+            file,
+            plan,
+            backed_up: vec![false; operations],
+            done: vec![false; operations],
+        })
+    }
+
+    /// Reads the journal of an interrupted update. A partially written last line is ignored, as
+    /// the step it describes was never recorded as finished, and cut off so new entries start on a
+    /// line of their own.
+    pub fn open(game_directory: &Path) -> std::io::Result<Journal> {
+        let path = game_directory.join(JOURNAL_FILE);
+        let mut reader = BufReader::new(File::open(&path)?);
+        let mut line = String::new();
+        let mut length = reader.read_line(&mut line)? as u64;
+        if line.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Empty journal"));
+        }
+        let plan: Plan = serde_json::from_str(&line)?;
+        let operations = plan.operations.len();
+        let mut backed_up = vec![false; operations];
+        let mut done = vec![false; operations];
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if !line.ends_with('\n') {
+                break;
+            }
+            // Entries for operations the plan does not have are as unusable as a partial line.
+            let Some(entry) = serde_json::from_str::<Entry>(&line).ok().filter(|entry| entry.operation < operations) else {
+                break;
+            };
+            match entry.stage {
+                Stage::BackedUp => backed_up[entry.operation] = true,
+                Stage::Done => done[entry.operation] = true,
+            }
+            length += read as u64;
+        }
+        let file = OpenOptions::new().append(true).open(&path)?;
+        file.set_len(length)?;
+        Ok(Journal {
+            path,
+            file,
+            plan,
+            backed_up,
+            done,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_backed_up(&self, operation: usize) -> bool {
+        self.backed_up[operation]
+    }
+
+    pub fn is_done(&self, operation: usize) -> bool {
+        self.done[operation]
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.done.iter().filter(|done| !**done).count()
+    }
+
+    pub fn record(&mut self, operation: usize, stage: Stage) -> std::io::Result<()> {
+        writeln!(self.file, "{}", serde_json::to_string(&Entry { operation, stage })?)?;
+        self.file.sync_data()?;
+        match stage {
+            Stage::BackedUp => self.backed_up[operation] = true,
+            Stage::Done => self.done[operation] = true,
+        }
+        Ok(())
+    }
+
+    /// Removes the journal once the update has been completed or rolled back.
+    pub fn finish(self) -> std::io::Result<()> {
+        drop(self.file);
+        std::fs::remove_file(self.path)
+    }
+}
@@ -1,21 +1,32 @@
-use std::fmt::Display;
-use std::fs::File;
-use std::path::{Path, PathBuf};
-use sha1::{Digest, Sha1};
+use std::fmt::{Display, Write};
+use std::fs::{metadata, File};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use crate::modules::archive::{find_update_path, read_entries};
 use crate::modules::changes::Changes;
+use crate::modules::error::{Error, Result};
+use crate::modules::files::{copy_and_hash, hash_file, list_files};
+use crate::modules::progress::Tracker;
+use crate::modules::output::{self, Event, Status, Task};
+
+/// Size of the chunks that Steam splits files into, used for the chunk count in DepotDownloader manifests.
+const CHUNK_SIZE: u64 = 1024 * 1024;
 
 
 #[derive(Debug, Clone)]
 pub struct GameFile {
     pub hash: String,
     pub name: String,
+    pub size: Option<u64>,
 }
 
 impl GameFile {
-    fn new(hash: String, name: String) -> Self {
+    pub fn new(hash: String, name: String) -> Self {
         Self {
             hash,
-            name
+            name,
+            size: None,
         }
     }
 }
@@ -35,58 +46,168 @@ impl Display for Manifest {
 }
 
 impl Manifest {
-    fn new(files: Vec<GameFile>) -> Self {
+    pub fn new(files: Vec<GameFile>) -> Self {
         Self {
             files
         }
     }
-    pub fn parse_manifest(path: &Option<PathBuf>) -> Option<Manifest> {
-        let file = match &path {
-            Some(file) => {
-                file
-            },
-            None => {
-                eprintln!("Please provide a manifest file (see the documentation).");
-                return None;
-            }
+
+    pub fn files(&self) -> &[GameFile] {
+        &self.files
+    }
+
+    /// Reads a DepotDownloader manifest (".txt") or a manifest viewer manifest (".sha1").
+    pub fn parse_manifest(file: &Path) -> Result<Manifest> {
+        let manifest = std::fs::read_to_string(file).map_err(Error::io(file))?;
+
+        match file.extension().and_then(|extension| extension.to_str()) {
+            Some("txt") => Ok(Manifest::new(parse_depot_download_manifest(&manifest))),
+            Some("sha1") => Ok(Manifest::new(parse_manifest_viewer_manifest(&manifest))),
+            _ => Err(Error::Parse {
+                path: file.to_path_buf(),
+                message: "Unsupported manifest file type".to_string(),
+            }),
+        }
+    }
+
+    /// Hashes every file in the directory (ignoring the installer and backup folders).
+    pub fn from_directory(directory: &Path) -> Result<Manifest> {
+        let names = list_files(directory).map_err(Error::io(directory))?;
+
+        let mut files = vec![];
+        for name in names {
+            output::message(&format!("Hashing {}...", name));
+            let path = directory.join(&name);
+            let hash = hash_file(&path).map_err(Error::io(&path))?;
+            let size = metadata(&path).map_err(Error::io(&path))?.len();
+            files.push(GameFile {
+                size: Some(size),
+                ..GameFile::new(hash, name)
+            });
+        }
+        Ok(Manifest::new(files))
+    }
+
+    /// Writes the manifest in the format given by the extension of the path, which is either a
+    /// DepotDownloader manifest (".txt") or a manifest viewer manifest (".sha1").
+    pub fn write_manifest(&self, path: &Path) -> Result<()> {
+        let manifest = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("txt") => self.to_depot_download_manifest(),
+            Some("sha1") => self.to_manifest_viewer_manifest(),
+            _ => return Err(Error::Parse {
+                path: path.to_path_buf(),
+                message: "Unsupported manifest file type".to_string(),
+            }),
         };
+        std::fs::write(path, manifest).map_err(Error::io(path))?;
+        output::message(&format!("Wrote {} files to {}.", self.files.len(), path.display()));
+        Ok(())
+    }
+
+    fn to_depot_download_manifest(&self) -> String {
+        let total_size: u64 = self.files.iter().filter_map(|file| file.size).sum();
+        let chunks = |size: u64| size.div_ceil(CHUNK_SIZE);
+        let mut manifest = String::new();
+        let _ = writeln!(manifest, "Content Manifest generated by RedAlt SteamUp Installer v{}\n", env!("CARGO_PKG_VERSION"));
+        let _ = writeln!(manifest, "Total number of files  : {}", self.files.len());
+        let _ = writeln!(manifest, "Total number of chunks : {}", self.files.iter().map(|file| chunks(file.size.unwrap_or(0))).sum::<u64>());
+        let _ = writeln!(manifest, "Total bytes on disk    : {}\n", total_size);
+        let _ = writeln!(manifest, "{:>14} {:>6} {:40} {:>5} Name", "Size", "Chunks", "File SHA", "Flags");
+        for file in &self.files {
+            let size = file.size.unwrap_or(0);
+            let _ = writeln!(manifest, "{:>14} {:>6} {:40} {:>5} {}", size, chunks(size), file.hash, 0, file.name);
+        }
+        manifest
+    }
 
-        let manifest = match std::fs::read_to_string(file) {
-            Ok(manifest) => manifest,
-            Err(error) => {
-                eprintln!("Error reading manifest file: {}", error);
-                return None;
+    fn to_manifest_viewer_manifest(&self) -> String {
+        let mut manifest = format!("; Generated by RedAlt SteamUp Installer v{}\n;\n", env!("CARGO_PKG_VERSION"));
+        for file in &self.files {
+            let _ = writeln!(manifest, "{} *{}", file.hash, file.name);
+        }
+        manifest
+    }
+
+    /// Validates the files added or modified by the changes (or every file if there are no
+    /// changes) in the directory, hashing them on the given number of threads.
+    pub fn validate_files(&self, directory: &Path, changes: Option<&Changes>, threads: usize) -> Result<()> {
+        validate(directory, &self.select_files(changes), threads)
+    }
+
+    /// Validates the files in an update archive without extracting it.
+    pub fn validate_archive(&self, archive: &Path, changes: Option<&Changes>) -> Result<()> {
+        output::message(&format!("Validating {}", archive.display()));
+        let game_files = self.select_files(changes);
+        let names: Vec<String> = game_files.iter().map(|game_file| game_file.name.replace('\\', "/")).collect();
+        let mut checked = vec![false; names.len()];
+        let mut mismatched = vec![];
+        let mut successes = 0;
+        let mut bytes = 0;
+        // Sizes are only known from DepotDownloader manifests, otherwise progress is counted in files.
+        let tracker = Tracker::new(Task::Validate, names.len(), game_files.iter().filter_map(|game_file| game_file.size).sum());
+
+        read_entries(archive, |name, entry| {
+            let Some(path) = find_update_path(name, &names) else {
+                return;
+            };
+            let index = names.iter().position(|name| name == path).unwrap();
+            checked[index] = true;
+            let mut sink = CountingSink(0);
+            match copy_and_hash(&mut tracker.reader(entry), &mut sink) {
+                Ok(hash) if hash == game_files[index].hash.to_lowercase() => {
+                    output::file(Task::Validate, path, Status::Ok, Some(sink.0), None,
+                                 format_args!("Validating {}...\tOk.", path));
+                    successes += 1;
+                },
+                Ok(_) => {
+                    output::file(Task::Validate, path, Status::Mismatch, Some(sink.0), None,
+                                 format_args!("Validating {}...\tHash mismatch.", path));
+                    mismatched.push(path.clone());
+                },
+                Err(error) => {
+                    output::file(Task::Validate, path, Status::Error, None, Some(error.to_string()),
+                                 format_args!("Validating {}...\tError: {}", path, error));
+                    mismatched.push(path.clone());
+                }
             }
-        };
+            bytes += sink.0;
+            tracker.finish_file();
+        }).map_err(Error::io(archive))?;
+
+        let mut missing = vec![];
+        for (name, checked) in names.iter().zip(checked) {
+            if !checked {
+                output::file(Task::Validate, name, Status::Missing, None, Some("Not found in archive".to_string()),
+                             format_args!("Validating {}...\tError: Not found in archive.", name));
+                missing.push(name.clone());
+            }
+        }
 
-        if path.as_ref().unwrap().extension().unwrap().to_str().unwrap() == "txt" {
-            let files = parse_depot_download_manifest(&manifest);
-            files.map(Manifest::new)
-        } else if path.as_ref().unwrap().extension().unwrap().to_str().unwrap() == "sha1" {
-            let files = parse_manifest_viewer_manifest(&manifest);
-            files.map(Manifest::new)
+        summary(names.len(), successes, &mismatched, &missing, bytes);
+        if !mismatched.is_empty() || !missing.is_empty() {
+            Err(Error::Validation { mismatched, missing })
         } else {
-            eprintln!("Unsupported manifest file type.");
-            None
+            Ok(())
         }
     }
 
-    pub fn validate_files(&self, directory: &Path, changes: Option<Changes>) -> Result<(), ()> {
-        println!("Validating {}", directory.display());
-        let mut hasher = Sha1::new();
-        let mut bad_files = vec![];
-        let mut mismatches = 0;
-        let mut missing = 0;
-        let mut successes = 0;
-        let mut total = 0;
+    /// Returns the expected hash of the file with the given path (using "/" as separator).
+    pub fn file_hash(&self, name: &str) -> Option<String> {
+        self.files.iter()
+            .find(|game_file| game_file.name.replace('\\', "/") == name)
+            .map(|game_file| game_file.hash.to_lowercase())
+    }
 
-        let game_files = match changes {
-            Some(mut changes) => {
-                let mut new_files = vec![];
-                new_files.append(&mut changes.added);
-                new_files.append(&mut changes.modified);
+    /// Returns the files that were added or modified by the changes, or every file if there are no changes.
+    /// Modified files with a patch are left out, as the patched file is checked when it is applied.
+    fn select_files(&self, changes: Option<&Changes>) -> Vec<GameFile> {
+        match changes {
+            Some(changes) => {
+                let new_files: Vec<&String> = changes.added.iter()
+                    .chain(changes.modified.iter().filter(|path| changes.delta(path).is_none()))
+                    .collect();
                 let files: Vec<GameFile> = self.files.iter()
-                    .filter(|&game_file| new_files.contains(&game_file.name.replace('\\', "/")))
+                    .filter(|&game_file| new_files.contains(&&game_file.name.replace('\\', "/")))
                     .cloned()
                     .collect();
                 files
@@ -94,77 +215,233 @@ impl Manifest {
             None => {
                 self.files.clone()
             }
-        };
-        for game_file in &game_files {
-            // let mut hasher = hasher.clone();
-            print!("Validating {}...\t", game_file.name);
-            let path = directory.join(&game_file.name);
-            if path.is_dir() {
-                continue;
-            }
-            total += 1;
-            let mut file = match File::open(path) {
-                Ok(file) => file,
-                Err(error) => {
-                    println!("Error: {}", error);
-                    missing += 1;
-                    bad_files.push(game_file.name.clone());
+        }
+    }
+
+    /// Validates the files that were not added or modified by the given changes, which are the
+    /// only files from the manifest that should still match after rolling back the update.
+    pub fn validate_unchanged_files(&self, directory: &Path, changes: &Changes, threads: usize) -> Result<()> {
+        let game_files: Vec<GameFile> = self.files.iter()
+            .filter(|&game_file| {
+                let name = game_file.name.replace('\\', "/");
+                !changes.added.contains(&name) && !changes.modified.contains(&name)
+            })
+            .cloned()
+            .collect();
+        validate(directory, &game_files, threads)
+    }
+}
+
+/// Outcome of validating a single file.
+enum Validation {
+    Ok,
+    Mismatch,
+    Missing,
+}
+
+/// Hashes the files using the given number of worker threads. Results are printed as soon as
+/// each file is checked, so they can appear out of order when using more than one thread.
+fn validate(directory: &Path, game_files: &[GameFile], threads: usize) -> Result<()> {
+    output::message(&format!("Validating {}", directory.display()));
+    let sizes: Vec<u64> = game_files.iter()
+        .map(|game_file| metadata(directory.join(&game_file.name)).map(|metadata| metadata.len()).unwrap_or(0))
+        .collect();
+    let tracker = Tracker::new(Task::Validate, game_files.len(), sizes.iter().sum());
+    let next = AtomicUsize::new(0);
+    let mut results = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.max(1)).map(|_| scope.spawn(|| {
+            let mut results = vec![];
+            loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(game_file) = game_files.get(index) else {
+                    break;
+                };
+                let path = directory.join(&game_file.name);
+                if path.is_dir() {
                     continue;
                 }
-            };
-
-            let _ = std::io::copy(&mut file, &mut hasher);
-            let hash = format!("{:x}", hasher.finalize_reset());
-            if hash == game_file.hash.to_lowercase() {
-                println!("Ok.");
-                successes += 1;
-            } else {
-                println!("Hash mismatch.");
-                bad_files.push(game_file.name.clone());
-                mismatches += 1;
+                let name = &game_file.name;
+                let bytes = sizes[index];
+                let hash = File::open(&path).and_then(|file| copy_and_hash(&mut tracker.reader(file), &mut std::io::sink()));
+                let validation = match hash {
+                    Ok(hash) if hash == game_file.hash.to_lowercase() => {
+                        output::file(Task::Validate, name, Status::Ok, Some(bytes), None,
+                                     format_args!("Validating {}...\tOk.", name));
+                        Validation::Ok
+                    },
+                    Ok(_) => {
+                        output::file(Task::Validate, name, Status::Mismatch, Some(bytes), None,
+                                     format_args!("Validating {}...\tHash mismatch.", name));
+                        Validation::Mismatch
+                    },
+                    Err(error) => {
+                        output::file(Task::Validate, name, Status::Missing, None, Some(error.to_string()),
+                                     format_args!("Validating {}...\tError: {}", name, error));
+                        Validation::Missing
+                    }
+                };
+                tracker.finish_file();
+                results.push((index, validation, bytes));
             }
-        }
+            results
+        })).collect();
+        workers.into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    results.sort_by_key(|(index, _, _)| *index);
 
-        println!("{} files checked, {} successes, {} mismatches, {} missing.", total, successes, mismatches, missing);
-        if !bad_files.is_empty() {
-            println!("Bad files:\n  {}", bad_files.join("\n  "));
-            Err(())
-        } else {
-            Ok(())
+    let mut mismatched = vec![];
+    let mut missing = vec![];
+    let mut successes = 0;
+    let mut bytes = 0;
+    for (index, validation, size) in &results {
+        bytes += size;
+        match validation {
+            Validation::Ok => successes += 1,
+            Validation::Mismatch => mismatched.push(game_files[*index].name.clone()),
+            Validation::Missing => missing.push(game_files[*index].name.clone()),
         }
     }
+
+    summary(results.len(), successes, &mismatched, &missing, bytes);
+    if !mismatched.is_empty() || !missing.is_empty() {
+        Err(Error::Validation { mismatched, missing })
+    } else {
+        Ok(())
+    }
 }
 
-fn parse_depot_download_manifest(manifest: &str) -> Option<Vec<GameFile>> {
+pub(crate) fn summary(files: usize, successes: usize, mismatched: &[String], missing: &[String], bytes: u64) {
+    output::emit(Event::Summary {
+        action: Task::Validate,
+        files,
+        successes,
+        failures: mismatched.len() + missing.len(),
+        bytes,
+    }, format_args!("{} files checked, {} successes, {} mismatches, {} missing.", files, successes, mismatched.len(), missing.len()));
+}
+
+/// Counts the bytes written to it and discards them.
+struct CountingSink(u64);
+
+impl std::io::Write for CountingSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn parse_depot_download_manifest(manifest: &str) -> Vec<GameFile> {
     let mut lines = manifest.lines();
     let _ = lines.position(|line| line.contains("Name"));
     let mut game_files = vec![];
     for line in lines {
-        let parts = line.split_whitespace().collect::<Vec<&str>>();
-        if let Some(hash) = parts.get(2) {
-            if let Some(name) = parts.get(4..) {
-                let game_file = GameFile::new(hash.to_string(), name.join("").to_string());
-                game_files.push(game_file);
-            }
+        // Size, chunks, hash and flags come before the name.
+        if let Some((columns, name)) = split_columns(line, 4) {
+            let game_file = GameFile {
+                size: columns[0].parse().ok(),
+                ..GameFile::new(columns[2].to_string(), name.to_string())
+            };
+            game_files.push(game_file);
         }
     }
-    Some(game_files)
+    game_files
 }
 
-fn parse_manifest_viewer_manifest(manifest: &str) -> Option<Vec<GameFile>> {
+fn parse_manifest_viewer_manifest(manifest: &str) -> Vec<GameFile> {
     let mut lines = manifest.lines();
     let _ = lines.position(|line| line.trim() == ";");
     let mut game_files = vec![];
     for line in lines {
-        let parts = line.split_whitespace().collect::<Vec<&str>>();
-        if let Some(hash) = parts.first() {
-            if let Some(name) = parts.get(1..) {
-                let game_file = GameFile::new(
-                    hash.to_string(),
-                    name.join(" ").replace('*', "").to_string());
-                game_files.push(game_file);
-            }
+        if let Some((columns, name)) = split_columns(line, 1) {
+            // The name is marked as binary ("*") or text (" ") after the space, like in sha1sum.
+            let name = name.strip_prefix(['*', ' ']).unwrap_or(name);
+            game_files.push(GameFile::new(columns[0].to_string(), name.to_string()));
+        }
+    }
+    game_files
+}
+
+/// Splits off the given number of columns separated by whitespace, and returns them with the rest
+/// of the line after the space that follows them, so names keep their spaces.
+fn split_columns(line: &str, count: usize) -> Option<(Vec<&str>, &str)> {
+    let mut columns = Vec::with_capacity(count);
+    let mut rest = line;
+    for _ in 0..count {
+        rest = rest.trim_start();
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        if end == 0 {
+            return None;
         }
+        columns.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    Some((columns, rest.strip_prefix(char::is_whitespace).unwrap_or(rest)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> Vec<GameFile> {
+        ["data/a.pak", "Name With Spaces.txt", "two  spaces/and\ttab.bin", "*star.txt"].iter().enumerate()
+            .map(|(index, name)| GameFile {
+                size: Some(index as u64 * 1_000_000),
+                ..GameFile::new(format!("{:040x}", index + 1), name.to_string())
+            })
+            .collect()
+    }
+
+    /// Writes the manifest to a file with the extension and parses it again.
+    fn round_trip(extension: &str) -> Vec<GameFile> {
+        let path = std::env::temp_dir().join(format!("redalt-manifest-{}.{}", std::process::id(), extension));
+        Manifest::new(files()).write_manifest(&path).unwrap();
+        let manifest = Manifest::parse_manifest(&path);
+        std::fs::remove_file(&path).unwrap();
+        manifest.unwrap().files().to_vec()
+    }
+
+    fn names(files: &[GameFile]) -> Vec<&str> {
+        files.iter().map(|file| file.name.as_str()).collect()
+    }
+
+    #[test]
+    fn depot_download_manifest_round_trip() {
+        let parsed = round_trip("txt");
+        assert_eq!(names(&parsed), names(&files()));
+        for (parsed, file) in parsed.iter().zip(files()) {
+            assert_eq!((&parsed.hash, parsed.size), (&file.hash, file.size));
+        }
+    }
+
+    #[test]
+    fn manifest_viewer_manifest_round_trip() {
+        let parsed = round_trip("sha1");
+        assert_eq!(names(&parsed), names(&files()));
+        for (parsed, file) in parsed.iter().zip(files()) {
+            assert_eq!(parsed.hash, file.hash);
+        }
+    }
+
+    #[test]
+    fn parses_depot_download_lines() {
+        let manifest = "Content Manifest for Depot 1\n\n          Size Chunks File SHA                                 Flags Name\n\
+                        \x20        12345      1 0123456789abcdef0123456789abcdef01234567     0 bin/Game Data.pak\n";
+        let files = parse_depot_download_manifest(manifest);
+        assert_eq!(names(&files), ["bin/Game Data.pak"]);
+        assert_eq!(files[0].size, Some(12345));
+        assert_eq!(files[0].hash, "0123456789abcdef0123456789abcdef01234567");
+    }
+
+    #[test]
+    fn parses_sha1sum_lines() {
+        let manifest = ";\n0123456789abcdef0123456789abcdef01234567 *binary name.txt\n\
+                        0123456789abcdef0123456789abcdef01234568  text name.txt\n";
+        assert_eq!(names(&parse_manifest_viewer_manifest(manifest)), ["binary name.txt", "text name.txt"]);
     }
-    Some(game_files)
 }
@@ -1,4 +1,14 @@
-
+pub mod archive;
+pub mod backup;
+pub mod chain;
 pub mod changes;
-pub mod settings;
-mod manifest;
+pub mod creator;
+pub mod delta;
+pub mod detect;
+pub mod error;
+pub mod files;
+pub mod journal;
+pub mod manifest;
+pub mod output;
+pub mod progress;
+pub mod update;
@@ -0,0 +1,116 @@
+use std::fmt::Display;
+use std::io::{stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use serde::Serialize;
+use crate::modules::error::{Error, Result};
+use crate::modules::progress::Progress;
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Switches between text for people and newline-delimited JSON events for front-ends.
+pub fn set_json(json: bool) {
+    JSON.store(json, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Task {
+    Validate,
+    Copy,
+    Backup,
+    Remove,
+    Restore,
+    Update,
+    Rollback,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    /// The file would be changed by the update (dry run).
+    Planned,
+    Mismatch,
+    Missing,
+    Error,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// A single file was validated, copied, backed up, removed or restored.
+    File {
+        action: Task,
+        path: &'a str,
+        status: Status,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        bytes: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// Totals of an action over all files.
+    Summary {
+        action: Task,
+        files: usize,
+        successes: usize,
+        failures: usize,
+        bytes: u64,
+    },
+    /// Files and bytes processed so far by a copy or validation, emitted at most once a second.
+    Progress(Progress),
+    /// Anything else shown to the user, like headings and the settings.
+    Message {
+        text: &'a str,
+    },
+    /// The command failed.
+    Error {
+        message: String,
+    },
+}
+
+/// Prints the text, or the event as a line of JSON in JSON mode.
+pub fn emit(event: Event, text: impl Display) {
+    let mut stdout = stdout().lock();
+    if is_json() {
+        // Events only hold strings and numbers, so they always serialise.
+        let _ = writeln!(stdout, "{}", serde_json::to_string(&event).unwrap());
+    } else {
+        let _ = writeln!(stdout, "{}", text);
+    }
+}
+
+/// Emits the event in JSON mode only, for events that have no line of text.
+pub fn event(event: Event) {
+    if is_json() {
+        emit(event, "");
+    }
+}
+
+pub fn message(text: &str) {
+    emit(Event::Message { text }, text);
+}
+
+/// Emits the result of a file operation, using the text for the outcome in text mode.
+pub fn file(action: Task, path: &str, status: Status, bytes: Option<u64>, error: Option<String>, text: impl Display) {
+    emit(Event::File { action, path, status, bytes, error }, text);
+}
+
+/// Emits the outcome of a file operation. The text is the same whether or not it failed, as
+/// errors are shown when the command finishes.
+pub fn outcome<T>(action: Task, path: &str, bytes: Option<u64>, result: &Result<T>, text: impl Display) {
+    let (status, error) = status(result);
+    file(action, path, status, bytes, error, text);
+}
+
+pub fn status<T>(result: &Result<T>) -> (Status, Option<String>) {
+    match result {
+        Ok(_) => (Status::Ok, None),
+        Err(error @ (Error::HashMismatch(_) | Error::PatchSource(_))) => (Status::Mismatch, Some(error.to_string())),
+        Err(error @ Error::MissingSource(_)) => (Status::Missing, Some(error.to_string())),
+        Err(error) => (Status::Error, Some(error.to_string())),
+    }
+}
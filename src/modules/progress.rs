@@ -0,0 +1,151 @@
+use std::fmt::Display;
+use std::io::Read;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use crate::modules::files::format_bytes;
+use crate::modules::output::{self, Event, Task};
+
+/// How often progress is shown on the terminal or emitted as a JSON event.
+const INTERVAL: Duration = Duration::from_secs(1);
+
+type Callback = Box<dyn Fn(&Progress) + Send + Sync>;
+
+static CALLBACK: RwLock<Option<Callback>> = RwLock::new(None);
+
+/// Sets a function that is called with the progress every time a file or a block of a file has
+/// been processed, for front-ends that show their own progress.
+pub fn set_callback(callback: impl Fn(&Progress) + Send + Sync + 'static) {
+    *CALLBACK.write().unwrap() = Some(Box::new(callback));
+}
+
+/// Progress of copying or validating a known set of files.
+#[derive(Clone, Copy, Serialize)]
+pub struct Progress {
+    pub action: Task,
+    pub files: usize,
+    pub total_files: usize,
+    pub bytes: u64,
+    pub total_bytes: u64,
+    /// Completed share of the bytes, or of the files if their sizes are not known.
+    pub percentage: f64,
+    pub bytes_per_second: f64,
+    /// Estimated seconds left, once anything has been processed.
+    pub eta_seconds: Option<f64>,
+}
+
+impl Display for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.1}% ({} of {} files", self.percentage, self.files, self.total_files)?;
+        if self.total_bytes > 0 {
+            write!(f, ", {} of {}", format_bytes(self.bytes), format_bytes(self.total_bytes))?;
+        }
+        write!(f, ") at {}/s", format_bytes(self.bytes_per_second as u64))?;
+        if let Some(eta) = self.eta_seconds {
+            write!(f, ", {} left", format_duration(eta as u64))?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts the files and bytes processed by an operation, which can be shared between threads.
+pub struct Tracker {
+    action: Task,
+    total_files: usize,
+    total_bytes: u64,
+    files: AtomicUsize,
+    bytes: AtomicU64,
+    start: Instant,
+    last_shown: Mutex<Instant>,
+}
+
+impl Tracker {
+    pub fn new(action: Task, total_files: usize, total_bytes: u64) -> Tracker {
+        Tracker {
+            action,
+            total_files,
+            total_bytes,
+            files: AtomicUsize::new(0),
+            bytes: AtomicU64::new(0),
+            start: Instant::now(),
+            last_shown: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wraps the reader so the bytes read from it are counted.
+    pub fn reader<R: Read>(&self, reader: R) -> TrackedReader<'_, R> {
+        TrackedReader { reader, tracker: self }
+    }
+
+    pub fn add_bytes(&self, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.report();
+    }
+
+    pub fn finish_file(&self) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.report();
+    }
+
+    pub fn progress(&self) -> Progress {
+        let files = self.files.load(Ordering::Relaxed);
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let done = if self.total_bytes > 0 {
+            bytes as f64 / self.total_bytes as f64
+        } else if self.total_files > 0 {
+            files as f64 / self.total_files as f64
+        } else {
+            1.0
+        }.min(1.0);
+        Progress {
+            action: self.action,
+            files,
+            total_files: self.total_files,
+            bytes,
+            total_bytes: self.total_bytes,
+            percentage: done * 100.0,
+            bytes_per_second: if elapsed > 0.0 { bytes as f64 / elapsed } else { 0.0 },
+            eta_seconds: (done > 0.0).then(|| elapsed / done - elapsed),
+        }
+    }
+
+    /// Passes the progress to the callback, and shows it at most once per interval.
+    fn report(&self) {
+        let progress = self.progress();
+        if let Some(callback) = CALLBACK.read().unwrap().as_ref() {
+            callback(&progress);
+        }
+        let mut last_shown = self.last_shown.lock().unwrap();
+        if last_shown.elapsed() >= INTERVAL {
+            *last_shown = Instant::now();
+            output::emit(Event::Progress(progress), format_args!("Progress: {}", progress));
+        }
+    }
+}
+
+pub struct TrackedReader<'a, R> {
+    reader: R,
+    tracker: &'a Tracker,
+}
+
+impl<R: Read> Read for TrackedReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.tracker.add_bytes(read as u64);
+        Ok(read)
+    }
+}
+
+/// Formats a number of seconds for display, e.g. "1h 02m 05s".
+fn format_duration(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h {:02}m {:02}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
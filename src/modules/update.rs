@@ -0,0 +1,688 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{create_dir, metadata, File};
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use crate::modules::archive::{find_update_path, is_zip, list_entries, read_entries};
+use crate::modules::changes::Changes;
+use crate::modules::delta::{apply_patch, Delta};
+use crate::modules::error::{Error, Result};
+use crate::modules::files::{copy_and_hash, display_name, format_bytes, hash_file};
+use crate::modules::journal::{Action, Journal, Operation, Plan, Stage, UpdateSource, JOURNAL_FILE};
+use crate::modules::manifest::{GameFile, Manifest};
+use crate::modules::output::{self, Event, Status, Task};
+use crate::modules::progress::Tracker;
+
+/// Where the new files of an update come from and which parts of the changes to apply.
+#[derive(Clone, Default)]
+pub struct UpdateOptions {
+    /// Folder holding the new files, used if there is no archive.
+    pub update_directory: Option<PathBuf>,
+    /// Zip or tar archive holding the new files.
+    pub update_archive: Option<PathBuf>,
+    /// Folder that replaced and removed files are copied to first, or `None` to skip backups.
+    pub backup_directory: Option<PathBuf>,
+    pub copy_files: bool,
+    pub remove_files: bool,
+}
+
+/// Lists the operations needed to apply the changes to the game directory.
+pub fn plan_update(game_directory: &Path, changes: &Changes, options: &UpdateOptions) -> Plan {
+    let mut operations = vec![];
+    if options.copy_files {
+        for path in changes.added.iter().chain(&changes.modified) {
+            if path.contains(".RedAlt-Steam-Installer") {
+                continue;
+            }
+            operations.push(Operation {
+                action: Action::Copy,
+                path: path.clone(),
+                existed: game_directory.join(path).is_file(),
+                delta: changes.delta(path),
+                update: 0,
+                repeated: false,
+            });
+        }
+    }
+    if options.remove_files {
+        for path in &changes.removed {
+            operations.push(Operation {
+                action: Action::Remove,
+                path: path.clone(),
+                existed: game_directory.join(path).is_file(),
+                delta: None,
+                update: 0,
+                repeated: false,
+            });
+        }
+    }
+    // Paths are stored as absolute paths so the update can be resumed from any directory.
+    let absolute = |path: &Option<PathBuf>| path.as_ref()
+        .map(|path| std::path::absolute(path).unwrap_or(path.clone()));
+    Plan {
+        update_directory: absolute(&options.update_directory),
+        update_archive: absolute(&options.update_archive),
+        backup_directory: absolute(&options.backup_directory),
+        operations,
+        chain: vec![],
+    }
+}
+
+/// Joins the plans of consecutive updates into one plan that applies them in order, using the
+/// backup directory of the first plan. Each file is backed up only before the first update that
+/// changes it, so the backup holds the game as it was before the chain.
+pub fn chain_plans(first: Plan, rest: Vec<Plan>) -> Plan {
+    let mut chain = first;
+    let mut changed: HashMap<String, bool> = chain.operations.iter()
+        .map(|operation| (operation.path.clone(), operation.existed))
+        .collect();
+    for (update, plan) in rest.into_iter().enumerate() {
+        chain.chain.push(UpdateSource {
+            update_directory: plan.update_directory,
+            update_archive: plan.update_archive,
+        });
+        for mut operation in plan.operations {
+            operation.update = update + 1;
+            match changed.get(&operation.path) {
+                Some(existed) => {
+                    operation.existed = *existed;
+                    operation.repeated = true;
+                },
+                None => {
+                    changed.insert(operation.path.clone(), operation.existed);
+                },
+            }
+            chain.operations.push(operation);
+        }
+    }
+    chain
+}
+
+/// Prints what the plan would do without changing any files. Fails if files are missing from the
+/// update.
+pub fn show_plan(game_directory: &Path, plan: &Plan) -> Result<()> {
+    let sizes = SourceSizes::new(plan, true)?;
+    let game_size = |path: &str| metadata(game_directory.join(path)).map(|metadata| metadata.len()).unwrap_or(0);
+
+    let spacing = 12;
+    let mut missing = vec![];
+    let (mut new_files, mut overwritten, mut deleted) = (0, 0, 0);
+    let (mut copied_bytes, mut backup_bytes, mut deleted_bytes) = (0, 0, 0);
+    output::message("Dry run, no files will be changed.");
+    for (index, operation) in plan.operations.iter().enumerate() {
+        if plan.updates() > 1 && (index == 0 || plan.operations[index - 1].update != operation.update) {
+            let source = plan.source(operation.update);
+            let source = source.update_archive.or(source.update_directory).unwrap_or_default();
+            output::message(&format!("\nUpdate {} of {} ({}):", operation.update + 1, plan.updates(), source.display()));
+        }
+        let update_size = |path: &str| sizes.size(operation.update, path);
+        let mut backup = String::new();
+        if plan.backup_directory.is_some() && operation.existed && !operation.repeated {
+            backup_bytes += game_size(&operation.path);
+            backup = format!(" (backup {})", format_bytes(game_size(&operation.path)));
+        }
+        match operation.action {
+            Action::Copy => {
+                let patch_size = operation.delta.as_ref().and_then(|delta| update_size(&delta.path));
+                let Some(size) = patch_size.or_else(|| update_size(&operation.path)) else {
+                    let error = Error::MissingSource(operation.path.clone());
+                    output::file(Task::Copy, &operation.path, Status::Missing, None, Some(error.to_string()),
+                                 format_args!("{:spacing$} {}", "Missing", operation.path));
+                    missing.push(error);
+                    continue;
+                };
+                copied_bytes += size;
+                if patch_size.is_some() {
+                    overwritten += 1;
+                    output::file(Task::Copy, &operation.path, Status::Planned, Some(size), None,
+                                 format_args!("{:spacing$} {} ({} patch){}", "Patch", operation.path, format_bytes(size), backup));
+                } else if operation.existed || operation.repeated {
+                    overwritten += 1;
+                    output::file(Task::Copy, &operation.path, Status::Planned, Some(size), None,
+                                 format_args!("{:spacing$} {} ({}){}", "Overwrite", operation.path, format_bytes(size), backup));
+                } else {
+                    new_files += 1;
+                    output::file(Task::Copy, &operation.path, Status::Planned, Some(size), None,
+                                 format_args!("{:spacing$} {} ({})", "Copy", operation.path, format_bytes(size)));
+                }
+            },
+            Action::Remove => {
+                if operation.existed || operation.repeated {
+                    deleted += 1;
+                    deleted_bytes += game_size(&operation.path);
+                    output::file(Task::Remove, &operation.path, Status::Planned, Some(game_size(&operation.path)), None,
+                                 format_args!("{:spacing$} {}{}", "Delete", operation.path, backup));
+                } else {
+                    output::message(&format!("{:spacing$} {} (not in game folder)", "Skip", operation.path));
+                }
+            },
+        }
+    }
+
+    let backup = match &plan.backup_directory {
+        Some(backup_directory) => format!("{} to back up to {}", format_bytes(backup_bytes), backup_directory.display()),
+        None => "backups disabled".to_string(),
+    };
+    output::message(&format!("\n{} new files and {} overwritten files to copy ({}), {} files to delete ({}), {}.",
+                             new_files, overwritten, format_bytes(copied_bytes), deleted, format_bytes(deleted_bytes), backup));
+    Error::collect(missing)
+}
+
+/// Checks that the files the update modifies or removes still match the initial build, so an
+/// update is never applied over the wrong build. The hashes are taken from the changes, or else
+/// from the manifest of the initial build.
+pub fn check_initial_build(game_directory: &Path, changes: &Changes, initial_manifest: Option<&Manifest>, threads: usize) -> Result<()> {
+    let files: Vec<GameFile> = changes.modified.iter().chain(&changes.removed)
+        .filter_map(|path| {
+            let hash = changes.old_hash(path)
+                .or_else(|| initial_manifest?.file_hash(path))?;
+            Some(GameFile::new(hash, path.clone()))
+        })
+        .collect();
+    if files.is_empty() && !(changes.modified.is_empty() && changes.removed.is_empty()) {
+        return Err(Error::MissingPath("a manifest of the initial build or a changes file with initial hashes"));
+    }
+
+    output::message(&format!("Checking the files of {} against the initial build.", folder_name(game_directory)));
+    Manifest::new(files).validate_files(game_directory, None, threads).map_err(|error| match error {
+        Error::Validation { mismatched, missing } => Error::WrongBuild { build: changes.initial_build.clone(), mismatched, missing },
+        error => error,
+    })
+}
+
+/// Applies the plan to the game directory. The plan is written to a journal first, so the update
+/// can be resumed or rolled back if it is interrupted. Files copied from an archive are checked
+/// against the manifest, if given.
+pub fn apply_update(game_directory: &Path, plan: Plan, manifest: Option<&Manifest>) -> Result<()> {
+    if Journal::exists(game_directory) {
+        return Err(Error::InterruptedUpdate(game_directory.to_path_buf()));
+    }
+    if let Some(backup_directory) = &plan.backup_directory {
+        if let Err(error) = create_dir(backup_directory) {
+            if error.kind() != ErrorKind::AlreadyExists {
+                return Err(Error::io(backup_directory)(error));
+            }
+        };
+    }
+    let journal = Journal::create(game_directory, plan).map_err(Error::io(&game_directory.join(JOURNAL_FILE)))?;
+    apply_journal(game_directory, journal, manifest)
+}
+
+/// Opens the journal of an interrupted update of the game directory.
+pub fn open_journal(game_directory: &Path) -> Result<Journal> {
+    if !Journal::exists(game_directory) {
+        return Err(Error::InvalidPath { path: game_directory.to_path_buf(), expected: "a directory with an interrupted update" });
+    }
+    let path = game_directory.join(JOURNAL_FILE);
+    Journal::open(game_directory).map_err(|error| match error.kind() {
+        ErrorKind::InvalidData => Error::Parse { path, message: error.to_string() },
+        _ => Error::Io { path, source: error },
+    })
+}
+
+/// Applies the remaining operations of the journal. The journal is kept if any operation fails,
+/// so the update can be resumed or rolled back later. The updates of a chain are applied one
+/// after the other, stopping at the first one that fails, as the next ones build on it.
+pub fn apply_journal(game_directory: &Path, mut journal: Journal, manifest: Option<&Manifest>) -> Result<()> {
+    let mut errors = vec![];
+    let tracker = copy_tracker(&journal)?;
+    for update in 0..journal.plan.updates() {
+        if !errors.is_empty() {
+            break;
+        }
+        let copied = match journal.plan.source(update) {
+            UpdateSource { update_archive: Some(archive), .. } => {
+                copy_files_from_archive(game_directory, &archive, &mut journal, update, manifest, &tracker)
+            },
+            source => copy_files(game_directory, source.update_directory.as_deref(), &mut journal, update, &tracker),
+        };
+        errors.extend(copied.err());
+        errors.extend(remove_files(game_directory, &mut journal, update).err());
+    }
+
+    let operations = &journal.plan.operations;
+    let bytes = (0..operations.len())
+        .filter(|index| operations[*index].action == Action::Copy && journal.is_done(*index))
+        .filter_map(|index| metadata(game_directory.join(&operations[index].path)).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    output::event(Event::Summary {
+        action: Task::Update,
+        files: operations.len(),
+        successes: operations.len() - journal.remaining(),
+        failures: journal.remaining(),
+        bytes,
+    });
+    if errors.is_empty() {
+        journal.finish().map_err(Error::io(&game_directory.join(JOURNAL_FILE)))?;
+    } else {
+        output::message(&format!("{} operations did not complete. Enter \"resume\" to retry them or \"rollback\" to undo the update.",
+                                 journal.remaining()));
+    }
+    Error::collect(errors)
+}
+
+/// Restores the game directory to its exact state before the interrupted update.
+pub fn rollback_journal(game_directory: &Path, journal: Journal) -> Result<()> {
+    let mut errors = vec![];
+    for index in (0..journal.plan.operations.len()).rev() {
+        let operation = &journal.plan.operations[index];
+        let old_file = game_directory.join(&operation.path);
+        let _ = std::fs::remove_file(partial_file(&old_file));
+        if operation.repeated {
+            // The first operation on the file restores it.
+            continue;
+        }
+        if !operation.existed {
+            if old_file.is_file() {
+                let result = std::fs::remove_file(&old_file).map_err(Error::io(&old_file));
+                output::outcome(Task::Remove, &operation.path, None, &result,
+                                format_args!("Removing {} from {}", operation.path, folder_name(game_directory)));
+                errors.extend(result.err());
+            }
+            continue;
+        }
+
+        if journal.is_backed_up(index) {
+            let backup_file = journal.plan.backup_directory.as_ref().unwrap().join(&operation.path);
+            let _ = std::fs::create_dir_all(old_file.parent().unwrap());
+            let result = std::fs::copy(&backup_file, &old_file).map_err(Error::io(&backup_file));
+            output::outcome(Task::Restore, &operation.path, result.as_ref().ok().copied(), &result,
+                            format_args!("Restoring {} to {}", operation.path, folder_name(game_directory)));
+            errors.extend(result.err());
+        } else if journal.is_done(index) {
+            errors.push(Error::MissingSource(format!("{} (no backup was made)", operation.path)));
+        }
+    }
+
+    Error::collect(errors)?;
+    journal.finish().map_err(Error::io(&game_directory.join(JOURNAL_FILE)))?;
+    output::message("Finished rolling back.");
+    Ok(())
+}
+
+/// Reverts a finished update using the backed-up files: the added files are removed and the
+/// modified and removed files are copied back from where `backup_file` finds them.
+pub fn restore_backup(game_directory: &Path, changes: &Changes, backup_file: impl Fn(&str) -> Option<PathBuf>) -> Result<()> {
+    let missing: Vec<&str> = backed_up_files(changes)
+        .filter(|path| !backup_file(path).is_some_and(|file| file.is_file()))
+        .collect();
+    check_backup(missing, "the backup")?;
+
+    let mut errors = remove_added_files(game_directory, changes);
+    for path in backed_up_files(changes) {
+        let old_file = game_directory.join(path);
+        let result = match backup_file(path) {
+            Some(backup_file) => {
+                let _ = std::fs::create_dir_all(old_file.parent().unwrap());
+                std::fs::copy(&backup_file, &old_file).map_err(Error::io(&backup_file))
+            },
+            None => Err(Error::MissingSource(format!("{} (not in the backup)", path))),
+        };
+        output::outcome(Task::Restore, path, result.as_ref().ok().copied(), &result,
+                        format_args!("Restoring {} to {}", path, folder_name(game_directory)));
+        errors.extend(result.err());
+    }
+    Error::collect(errors)
+}
+
+/// Reverts a finished update using a compressed backup, reading the archive once.
+pub fn restore_backup_archive(game_directory: &Path, archive: &Path, changes: &Changes) -> Result<()> {
+    let entries: HashSet<String> = list_entries(archive).map_err(Error::io(archive))?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    let missing: Vec<&str> = backed_up_files(changes).filter(|path| !entries.contains(*path)).collect();
+    check_backup(missing, &archive.display().to_string())?;
+
+    let mut errors = remove_added_files(game_directory, changes);
+    let mut restored: HashMap<&str, bool> = backed_up_files(changes).map(|path| (path, false)).collect();
+    read_entries(archive, |name, entry| {
+        let Some(done) = restored.get_mut(name) else {
+            return;
+        };
+        *done = true;
+        let old_file = game_directory.join(name);
+        let _ = std::fs::create_dir_all(old_file.parent().unwrap());
+        let result = File::create(&old_file)
+            .and_then(|mut file| std::io::copy(entry, &mut file))
+            .map_err(Error::io(&old_file));
+        output::outcome(Task::Restore, name, result.as_ref().ok().copied(), &result,
+                        format_args!("Restoring {} to {}", name, folder_name(game_directory)));
+        errors.extend(result.err());
+    }).map_err(Error::io(archive))?;
+
+    for (path, _) in restored.into_iter().filter(|(_, done)| !done) {
+        let result: Result<()> = Err(Error::MissingSource(format!("{} (not in {})", path, archive.display())));
+        output::outcome(Task::Restore, path, None, &result,
+                        format_args!("Restoring {} to {}", path, folder_name(game_directory)));
+        errors.extend(result.err());
+    }
+    Error::collect(errors)
+}
+
+/// Lists the files the update modified or removed, which the backup has to hold.
+fn backed_up_files(changes: &Changes) -> impl Iterator<Item = &str> {
+    changes.modified.iter().chain(&changes.removed)
+        .filter(|path| !path.contains(".RedAlt-Steam-Installer"))
+        .map(String::as_str)
+}
+
+/// Refuses to restore anything if some files are missing from the backup, so the game is left as
+/// it is instead of half reverted.
+fn check_backup(missing: Vec<&str>, backup: &str) -> Result<()> {
+    Error::collect(missing.into_iter()
+        .map(|path| Error::MissingSource(format!("{} (not in {})", path, backup)))
+        .collect())
+}
+
+/// Removes the files added by the update, and the folders that only existed for them.
+fn remove_added_files(game_directory: &Path, changes: &Changes) -> Vec<Error> {
+    let mut errors = vec![];
+    for path in &changes.added {
+        if path.contains(".RedAlt-Steam-Installer") {
+            continue;
+        }
+
+        let old_file = game_directory.join(path);
+        let result = match std::fs::remove_file(&old_file) {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(Error::io(&old_file)(error)),
+            _ => Ok(()),
+        };
+        output::outcome(Task::Remove, path, None, &result,
+                        format_args!("Removing {} from {}", path, folder_name(game_directory)));
+        if let Err(error) = result {
+            errors.push(error);
+            continue;
+        }
+        // Clean up directories that only existed for the added files.
+        let mut parent = old_file.parent();
+        while let Some(directory) = parent {
+            if directory == game_directory || std::fs::remove_dir(directory).is_err() {
+                break;
+            }
+            parent = directory.parent();
+        }
+    }
+    errors
+}
+
+/// Counts the files left to copy and their sizes (or the sizes of their patches), which are read
+/// from the update directory or, for zip archives, from the archive index. Other archives would
+/// have to be read in full first, so their progress is counted in files.
+fn copy_tracker(journal: &Journal) -> Result<Tracker> {
+    let sizes = SourceSizes::new(&journal.plan, false)?;
+    let operations: Vec<&Operation> = journal.plan.operations.iter().enumerate()
+        .filter(|(index, operation)| operation.action == Action::Copy && !journal.is_done(*index))
+        .map(|(_, operation)| operation)
+        .collect();
+    let total_bytes = operations.iter()
+        .filter_map(|operation| operation.delta.as_ref()
+            .and_then(|delta| sizes.size(operation.update, &delta.path))
+            .or_else(|| sizes.size(operation.update, &operation.path)))
+        .sum();
+    Ok(Tracker::new(Task::Copy, operations.len(), total_bytes))
+}
+
+/// Sizes of the new files in the folders and archives of a plan.
+struct SourceSizes {
+    sources: Vec<UpdateSource>,
+    /// Files in each archive by their path, with and without the top folder of the archive.
+    entries: Vec<HashMap<String, u64>>,
+}
+
+impl SourceSizes {
+    /// Lists the archives of the plan. Only zip archives are listed unless `read_archives` is set,
+    /// as other archives have to be read in full.
+    fn new(plan: &Plan, read_archives: bool) -> Result<SourceSizes> {
+        let sources: Vec<UpdateSource> = (0..plan.updates()).map(|update| plan.source(update)).collect();
+        let mut entries = vec![];
+        for source in &sources {
+            let mut files = HashMap::new();
+            if let Some(archive) = source.update_archive.as_ref().filter(|archive| read_archives || is_zip(archive)) {
+                for (name, size) in list_entries(archive).map_err(Error::io(archive))? {
+                    if let Some((_, path)) = name.split_once('/') {
+                        files.entry(path.to_string()).or_insert(size);
+                    }
+                    files.insert(name, size);
+                }
+            }
+            entries.push(files);
+        }
+        Ok(SourceSizes { sources, entries })
+    }
+
+    fn size(&self, update: usize, path: &str) -> Option<u64> {
+        match &self.sources[update] {
+            UpdateSource { update_archive: Some(_), .. } => self.entries[update].get(path).copied(),
+            UpdateSource { update_directory: Some(update_directory), .. } => {
+                metadata(update_directory.join(path)).ok().map(|metadata| metadata.len())
+            },
+            _ => None,
+        }
+    }
+}
+
+fn copy_files(game_directory: &Path, update_directory: Option<&Path>, journal: &mut Journal, update: usize, tracker: &Tracker) -> Result<()> {
+    let update_directory = update_directory.ok_or(Error::MissingPath("an update directory or archive"))?;
+    let mut errors = vec![];
+    for index in 0..journal.plan.operations.len() {
+        let operation = &journal.plan.operations[index];
+        if operation.action != Action::Copy || operation.update != update || journal.is_done(index) {
+            continue;
+        }
+
+        let new_file = update_directory.join(&operation.path);
+        if let Some(delta) = operation.delta.clone() {
+            let patch_file = update_directory.join(&delta.path);
+            if patch_file.is_file() {
+                let patched = File::open(&patch_file).map_err(Error::io(&patch_file))
+                    .and_then(|patch| copy_file(game_directory, journal, index, Source::Patch(&delta, &mut tracker.reader(patch)), None));
+                match patched {
+                    Err(error) if new_file.is_file() => {
+                        output::message(&format!("{}. Copying the full file instead.", error));
+                    },
+                    patched => {
+                        errors.extend(patched.err());
+                        tracker.finish_file();
+                        continue;
+                    },
+                }
+            }
+        }
+
+        let new_file = match File::open(&new_file) {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                errors.push(Error::MissingSource(journal.plan.operations[index].path.clone()));
+                continue;
+            },
+            Err(error) => {
+                errors.push(Error::io(&new_file)(error));
+                continue;
+            }
+        };
+        errors.extend(copy_file(game_directory, journal, index, Source::File(&mut tracker.reader(new_file)), None).err());
+        tracker.finish_file();
+    }
+    Error::collect(errors)
+}
+
+/// Copies the added and modified files straight out of the archive, checking each file against
+/// the manifest (if given) before it replaces the game file. Patches are applied as they are
+/// found, and the full file is used instead if a patch fails.
+fn copy_files_from_archive(game_directory: &Path, archive: &Path, journal: &mut Journal, update: usize, manifest: Option<&Manifest>, tracker: &Tracker) -> Result<()> {
+    let operations: Vec<usize> = (0..journal.plan.operations.len())
+        .filter(|index| {
+            let operation = &journal.plan.operations[*index];
+            operation.action == Action::Copy && operation.update == update && !journal.is_done(*index)
+        })
+        .collect();
+    let new_files: Vec<String> = operations.iter()
+        .map(|index| journal.plan.operations[*index].path.clone())
+        .collect();
+    let deltas: Vec<Option<Delta>> = operations.iter()
+        .map(|index| journal.plan.operations[*index].delta.clone())
+        .collect();
+    let patch_files: Vec<String> = deltas.iter().flatten().map(|delta| delta.path.clone()).collect();
+    let mut copied = vec![false; new_files.len()];
+    let mut patch_errors: Vec<Option<Error>> = new_files.iter().map(|_| None).collect();
+    let mut errors = vec![];
+
+    read_entries(archive, |name, entry| {
+        if let Some(path) = find_update_path(name, &new_files) {
+            let index = new_files.iter().position(|new_file| new_file == path).unwrap();
+            if copied[index] {
+                return;
+            }
+            copied[index] = true;
+            patch_errors[index] = None;
+            errors.extend(copy_file(game_directory, journal, operations[index], Source::File(&mut tracker.reader(entry)), manifest).err());
+            tracker.finish_file();
+        } else if let Some(path) = find_update_path(name, &patch_files) {
+            let index = deltas.iter().position(|delta| delta.as_ref().is_some_and(|delta| &delta.path == path)).unwrap();
+            if copied[index] {
+                return;
+            }
+            let delta = deltas[index].as_ref().unwrap();
+            match copy_file(game_directory, journal, operations[index], Source::Patch(delta, &mut tracker.reader(entry)), manifest) {
+                Ok(()) => {
+                    copied[index] = true;
+                    tracker.finish_file();
+                },
+                Err(error) => patch_errors[index] = Some(error),
+            }
+        }
+    }).map_err(Error::io(archive))?;
+
+    for ((path, copied), patch_error) in new_files.iter().zip(copied).zip(patch_errors) {
+        if !copied {
+            errors.push(patch_error.unwrap_or_else(|| Error::MissingSource(path.clone())));
+        }
+    }
+    Error::collect(errors)
+}
+
+/// Where the new version of a game file comes from.
+enum Source<'a> {
+    /// The whole new file.
+    File(&'a mut dyn Read),
+    /// A patch that is applied to the game file.
+    Patch(&'a Delta, &'a mut dyn Read),
+}
+
+/// Backs up the game file and replaces it with the new file, checking the new file against the
+/// manifest (if given) or the hash of its patch first. A patch is only applied if the game file
+/// matches the file it was made from. Each step is recorded in the journal.
+fn copy_file(game_directory: &Path, journal: &mut Journal, index: usize, source: Source, manifest: Option<&Manifest>) -> Result<()> {
+    let operation = &journal.plan.operations[index];
+    let path = operation.path.clone();
+    let expected = manifest.and_then(|manifest| manifest.file_hash(&path))
+        .or_else(|| Some(operation.delta.as_ref()?.result_sha1.clone()));
+    let old_file = game_directory.join(&path);
+    let partial_file = partial_file(&old_file);
+    let verb = match source {
+        Source::File(_) => format!("Copying {} to", path),
+        Source::Patch(..) => format!("Patching {} in", path),
+    };
+    let _ = std::fs::create_dir_all(old_file.parent().unwrap());
+    let result = check_patch_source(&old_file, &path, &source)
+        .and_then(|_| File::create(&partial_file)
+            .and_then(|mut file| {
+                let hash = match source {
+                    Source::File(new_file) => copy_and_hash(new_file, &mut file)?,
+                    Source::Patch(_, patch) => apply_patch(&old_file, patch, &mut file)?,
+                };
+                file.sync_all()?;
+                Ok((hash, file.metadata()?.len()))
+            })
+            .map_err(Error::io(&partial_file)))
+        .and_then(|(hash, bytes)| match expected {
+            Some(expected) if hash != expected => Err(Error::HashMismatch(path.clone())),
+            _ => Ok(bytes),
+        })
+        .and_then(|bytes| backup_file(game_directory, journal, index).map(|_| bytes))
+        .and_then(|bytes| std::fs::rename(&partial_file, &old_file).map(|_| bytes).map_err(Error::io(&old_file)));
+    output::outcome(Task::Copy, &path, result.as_ref().ok().copied(), &result,
+                    format_args!("{} {}", verb, folder_name(game_directory)));
+    if let Err(error) = result {
+        let _ = std::fs::remove_file(&partial_file);
+        return Err(error);
+    }
+    record(journal, index, Stage::Done)
+}
+
+/// Checks that the game file is the one the patch was made from, if the file is being patched.
+fn check_patch_source(old_file: &Path, path: &str, source: &Source) -> Result<()> {
+    let Source::Patch(delta, _) = source else {
+        return Ok(());
+    };
+    match hash_file(old_file) {
+        Ok(hash) if hash == delta.source_sha1 => Ok(()),
+        Err(error) if error.kind() != ErrorKind::NotFound => Err(Error::io(old_file)(error)),
+        _ => Err(Error::PatchSource(path.to_string())),
+    }
+}
+
+/// Copies the game file of the operation to the backup directory, unless backups are disabled,
+/// the file did not exist, an earlier update of the chain changed it or it has already been
+/// backed up.
+fn backup_file(game_directory: &Path, journal: &mut Journal, index: usize) -> Result<()> {
+    let operation = &journal.plan.operations[index];
+    let Some(backup_directory) = &journal.plan.backup_directory else {
+        return Ok(());
+    };
+    if !operation.existed || operation.repeated || journal.is_backed_up(index) {
+        return Ok(());
+    }
+
+    let old_file = game_directory.join(&operation.path);
+    let backup_file = backup_directory.join(&operation.path);
+    let _ = std::fs::create_dir_all(backup_file.parent().unwrap());
+    let result = std::fs::copy(&old_file, &backup_file)
+        .and_then(|bytes| File::open(&backup_file)?.sync_all().map(|_| bytes))
+        .map_err(Error::io(&backup_file));
+    let (status, error) = output::status(&result);
+    output::event(Event::File { action: Task::Backup, path: &operation.path, status, bytes: result.as_ref().ok().copied(), error });
+    result?;
+    record(journal, index, Stage::BackedUp)
+}
+
+fn remove_files(game_directory: &Path, journal: &mut Journal, update: usize) -> Result<()> {
+    let mut errors = vec![];
+    for index in 0..journal.plan.operations.len() {
+        let operation = &journal.plan.operations[index];
+        if operation.action != Action::Remove || operation.update != update || journal.is_done(index) {
+            continue;
+        }
+
+        let path = operation.path.clone();
+        let old_file = game_directory.join(&path);
+        let result = backup_file(game_directory, journal, index).and_then(|_| match std::fs::remove_file(&old_file) {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(Error::io(&old_file)(error)),
+            _ => Ok(()),
+        });
+        output::outcome(Task::Remove, &path, None, &result,
+                        format_args!("Removing {} from {}", path, folder_name(game_directory)));
+        if let Err(error) = result {
+            errors.push(error);
+            continue;
+        }
+        record(journal, index, Stage::Done)?;
+    }
+    Error::collect(errors)
+}
+
+fn folder_name(directory: &Path) -> String {
+    display_name(directory)
+}
+
+fn partial_file(path: &Path) -> PathBuf {
+    let mut partial_file = path.as_os_str().to_owned();
+    partial_file.push(".partial");
+    PathBuf::from(partial_file)
+}
+
+fn record(journal: &mut Journal, index: usize, stage: Stage) -> Result<()> {
+    let path = journal.path().to_path_buf();
+    journal.record(index, stage).map_err(Error::io(&path))
+}
@@ -0,0 +1,1075 @@
+use std::env::{current_dir, var_os};
+use std::fmt::Display;
+use std::fs::{create_dir_all, read_dir};
+use std::path::{Path, PathBuf};
+use std::thread::available_parallelism;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use redalt_steamup_installer::modules::archive::is_archive;
+use redalt_steamup_installer::modules::backup::{self, Backup, BackupFormat, BackupIndex, BACKUP_DIRECTORY};
+use redalt_steamup_installer::modules::chain::{self, Package};
+use redalt_steamup_installer::modules::creator;
+use redalt_steamup_installer::modules::detect::{self, Fingerprint};
+use redalt_steamup_installer::modules::files::{display_name, format_bytes};
+use redalt_steamup_installer::modules::output;
+use redalt_steamup_installer::modules::update::{self, UpdateOptions};
+use redalt_steamup_installer::{Changes, Error, Journal, Manifest, Result};
+use crate::get_input;
+
+/// Name of the settings file saved in the installer folder.
+const SETTINGS_FILE: &str = "RedAlt-SteamUp-Installer.json";
+
+/// What to do when the files that an update changes do not match its initial build.
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum BuildCheck {
+    Refuse,
+    Warn,
+    Off,
+}
+
+#[derive(Serialize)]
+pub struct Settings {
+    pub changes_file: Option<PathBuf>,
+    game_directory: Option<PathBuf>,
+    update_directory: Option<PathBuf>,
+    update_archive: Option<PathBuf>,
+    manifest_file: Option<PathBuf>,
+    initial_manifest_file: Option<PathBuf>,
+    check_initial_build: BuildCheck,
+    validate_update: bool,
+    validate_game: bool,
+    validation_threads: usize,
+    create_backup: bool,
+    backup_format: BackupFormat,
+    dry_run: bool,
+    copy_files: bool,
+    remove_files: bool,
+    #[serde(skip)]
+    pub assume_yes: bool,
+    #[serde(skip)]
+    pub ignore_validation: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            changes_file: {
+                let mut files = read_dir(current_dir().unwrap()).unwrap();
+                files
+                    .find(|file| file.as_ref().unwrap().file_name().to_str().unwrap().contains("changes.json"))
+                    .map(|file| file.unwrap().path())
+            },
+            game_directory: {
+                let current_directory = current_dir().unwrap();
+                let parent = current_directory.parent().unwrap();
+                if let Some(grandparent) = parent.parent() {
+                    Some(grandparent.to_path_buf())
+                } else {
+                    Some(parent.to_path_buf())
+                }
+            },
+            update_directory: Some(current_dir().unwrap().parent().unwrap().to_path_buf()),
+            update_archive: None,
+            manifest_file: {
+                let mut files = read_dir(current_dir().unwrap()).unwrap();
+                files
+                    .find(|file| {
+                        let file_name = file.as_ref().unwrap().file_name();
+                        let file_name = file_name.to_str().unwrap();
+                        file_name.contains("manifest") || file_name.contains("sha1")
+                    })
+                    .map(|file| file.unwrap().path())
+            },
+            initial_manifest_file: None,
+            check_initial_build: BuildCheck::Refuse,
+            validate_update: true,
+            validate_game: true,
+            validation_threads: available_parallelism().map(|threads| threads.get()).unwrap_or(1),
+            create_backup: true,
+            backup_format: BackupFormat::Folder,
+            dry_run: false,
+            copy_files: true,
+            remove_files: true,
+            assume_yes: false,
+            ignore_validation: false,
+        }
+    }
+}
+
+impl Display for Settings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let spacing = 45;
+        writeln!(f, "{:spacing$} {}", "Using changes file (changes_file):", match &self.changes_file {
+            Some(path) => path.to_str().unwrap(),
+            None => "None",
+        })?;
+        writeln!(f, "{:spacing$} {}", "Using game directory (game_directory):", match &self.game_directory {
+            Some(path) => path.to_str().unwrap(),
+            None => "None",
+        })?;
+        writeln!(f, "{:spacing$} {}", "Using update directory (update_directory):", match &self.update_directory {
+            Some(path) => path.to_str().unwrap(),
+            None => "None",
+        })?;
+        writeln!(f, "{:spacing$} {}", "Using update archive (update_archive):", match &self.update_archive {
+            Some(path) => path.to_str().unwrap(),
+            None => "None",
+        })?;
+        writeln!(f, "{:spacing$} {}", "Using manifest file (manifest_file):", match &self.manifest_file {
+            Some(path) => path.to_str().unwrap(),
+            None => "None",
+        })?;
+        writeln!(f, "{:spacing$} {}", "Initial manifest (initial_manifest_file):", match &self.initial_manifest_file {
+            Some(path) => path.to_str().unwrap(),
+            None => "None",
+        })?;
+        writeln!(f, "{:spacing$} {}", "Check initial build (check_initial_build):", match self.check_initial_build {
+            BuildCheck::Refuse => "refuse",
+            BuildCheck::Warn => "warn",
+            BuildCheck::Off => "off",
+        })?;
+        writeln!(f, "{:spacing$} {}", "Validate update files (validate_update):", match &self.manifest_file {
+            Some(_) => self.validate_update.to_string(),
+            None => format!("{} (requires hashes in the changes file)", self.validate_update),
+        })?;
+        writeln!(f, "{:spacing$} {}", "Validate game files (validate_game):", match &self.manifest_file {
+            Some(_) => self.validate_game.to_string(),
+            None => format!("{} (requires hashes in the changes file)", self.validate_game),
+        })?;
+        writeln!(f, "{:spacing$} {}", "Validation threads (validation_threads):", self.validation_threads)?;
+        writeln!(f, "{:spacing$} {}", "Create backup (create_backup):", self.create_backup)?;
+        writeln!(f, "{:spacing$} {}", "Backup format (backup_format):", self.backup_format)?;
+        writeln!(f, "{:spacing$} {}", "Dry run (dry_run):", self.dry_run)?;
+        writeln!(f, "{:spacing$} {}", "Copy files (copy_files):", self.copy_files)?;
+        write!(f, "{:spacing$} {}", "Remove files (remove_files):", self.remove_files)?;
+        Ok(())
+    }
+}
+
+impl Settings {
+    /// Returns the default settings, overridden by the per-user settings file and then by the
+    /// settings file in the installer folder.
+    pub fn load() -> Self {
+        let mut settings = Settings::default();
+        for file in [user_settings_file(), Some(current_dir().unwrap().join(SETTINGS_FILE))].into_iter().flatten() {
+            if file.is_file() {
+                if let Err(error) = settings.load_file(&file) {
+                    eprintln!("Error loading settings: {}", error);
+                }
+            }
+        }
+        settings
+    }
+
+    /// Applies every field in the settings file. Fields set to null keep their current value.
+    fn load_file(&mut self, path: &Path) -> Result<()> {
+        let fields = std::fs::read_to_string(path).map_err(Error::io(path))?;
+        let fields = serde_json::from_str::<Map<String, Value>>(&fields).map_err(|error| Error::Parse {
+            path: path.to_path_buf(),
+            message: error.to_string(),
+        })?;
+
+        let mut errors = vec![];
+        for (field, value) in fields {
+            let value = match value {
+                Value::Null => continue,
+                Value::String(value) => value,
+                Value::Bool(value) => value.to_string(),
+                Value::Number(value) => value.to_string(),
+                value => {
+                    errors.push(Error::InvalidValue { field, value: value.to_string() });
+                    continue;
+                }
+            };
+            if let Err(error) = self.set_field(&field, &value) {
+                errors.push(error);
+            }
+        }
+        output::message(&format!("Loaded settings from {}.", path.display()));
+        Error::collect(errors)
+    }
+
+    /// Saves the settings to the file. Paths are saved as absolute paths, and left out entirely
+    /// when `include_paths` is false, as they only make sense for one installer folder.
+    fn save_file(&self, path: &Path, include_paths: bool) -> Result<()> {
+        // Settings always serialise to an object.
+        let Ok(Value::Object(mut fields)) = serde_json::to_value(self) else {
+            unreachable!();
+        };
+        let is_path = |field: &str| field.ends_with("_file") || field.ends_with("_directory") || field.ends_with("_archive");
+        fields.retain(|field, _| include_paths || !is_path(field));
+        for (field, value) in fields.iter_mut() {
+            if let Value::String(field_path) = value {
+                if !is_path(field) {
+                    continue;
+                }
+                if let Ok(absolute) = std::path::absolute(field_path.as_str()) {
+                    *field_path = absolute.display().to_string();
+                }
+            }
+        }
+        let settings = serde_json::to_string_pretty(&fields).unwrap();
+        if let Some(parent) = path.parent() {
+            let _ = create_dir_all(parent);
+        }
+        std::fs::write(path, settings).map_err(Error::io(path))?;
+        output::message(&format!("Saved settings to {}.", path.display()));
+        Ok(())
+    }
+
+    /// Handles the "settings" command: shows, saves, loads or resets the settings.
+    pub fn manage_settings(&mut self, arguments: &[&str]) -> Result<()> {
+        let argument = arguments.get(1..).map(|rest| rest.join(" ").replace('"', "")).unwrap_or_default();
+        match arguments.first() {
+            None => {
+                output::message(&self.to_string());
+                Ok(())
+            },
+            Some(&"save") => match argument.as_str() {
+                "" => self.save_file(&current_dir().unwrap().join(SETTINGS_FILE), true),
+                "user" => match user_settings_file() {
+                    Some(file) => self.save_file(&file, false),
+                    None => Err(Error::MissingPath("a user settings folder (set HOME, APPDATA or XDG_CONFIG_HOME)")),
+                },
+                file => self.save_file(Path::new(file), true),
+            },
+            Some(&"load") => {
+                if argument.is_empty() {
+                    return Err(Error::MissingPath("the settings file to load"));
+                }
+                let result = self.load_file(Path::new(&argument));
+                output::message(&self.to_string());
+                result
+            },
+            Some(&"reset") => {
+                *self = Settings {
+                    assume_yes: self.assume_yes,
+                    ignore_validation: self.ignore_validation,
+                    ..Settings::default()
+                };
+                output::message(&format!("Reset settings to the defaults. Enter \"settings save\" to keep them.\n{}", self));
+                Ok(())
+            },
+            Some(command) => Err(Error::Usage(format!("Unknown command \"settings {}\". Enter \"settings\", \"settings save\", \"load\" or \"reset\".", command))),
+        }
+    }
+
+    pub fn modify_fields(&mut self, input: String) {
+        let input = input.split(' ').collect::<Vec<&str>>();
+        let field = match input.get(1) {
+            Some(field) => field.to_owned(),
+            None => {
+                eprintln!("Enter a field.");
+                return;
+            }
+        };
+        // Rest of input
+        // let value = input[2..].join(" ").replace('"', "").trim().to_string();
+        let value = match input.get(2) {
+            Some(_) => { input[2..].join(" ").replace('"', "").trim().to_string() }
+            None => {
+                eprintln!("Enter a value.");
+                return;
+            }
+        };
+        if let Err(error) = self.set_field(field, &value) {
+            eprintln!("Error: {}", error);
+        }
+
+        println!("{}", self);
+    }
+
+    pub fn set_field(&mut self, field: &str, value: &str) -> Result<()> {
+        let invalid_value = || Error::InvalidValue { field: field.to_string(), value: value.to_string() };
+        let parse_bool = |value: &str| {
+            let value = value.to_lowercase();
+            if ["true", "t", "1"].contains(&value.as_str()) {
+                Ok(true)
+            } else if ["false", "f", "0"].contains(&value.as_str()) {
+                Ok(false)
+            } else {
+                Err(invalid_value())
+            }
+        };
+        // Invalid paths clear the field, so a stale path is never used by mistake.
+        let parse_path = |value: &str, valid: bool, expected: &'static str| {
+            let path = PathBuf::from(value);
+            if valid {
+                (Some(path), Ok(()))
+            } else {
+                (None, Err(Error::InvalidPath { path, expected }))
+            }
+        };
+        let parse_file = |value: &str| parse_path(value, Path::new(value).is_file(), "a file");
+        let parse_directory = |value: &str| parse_path(value, Path::new(value).is_dir(), "a directory");
+        match field {
+            "changes_file" => {
+                let (path, result) = parse_file(value);
+                self.changes_file = path;
+                result
+            },
+            "game_directory" => {
+                let (path, result) = parse_directory(value);
+                self.game_directory = path;
+                result
+            },
+            "update_directory" => {
+                let (path, result) = parse_directory(value);
+                self.update_directory = path;
+                result
+            },
+            "update_archive" => {
+                let (path, result) = parse_path(value, Path::new(value).is_file() && is_archive(Path::new(value)),
+                                                "a \".zip\", \".tar\", \".tar.gz\" or \".tar.zst\" file");
+                self.update_archive = path;
+                result
+            },
+            "manifest_file" => {
+                let (path, result) = parse_file(value);
+                self.manifest_file = path;
+                result
+            },
+            "initial_manifest_file" => {
+                let (path, result) = parse_file(value);
+                self.initial_manifest_file = path;
+                result
+            },
+            "check_initial_build" => {
+                self.check_initial_build = match value.to_lowercase().as_str() {
+                    "refuse" => BuildCheck::Refuse,
+                    "warn" => BuildCheck::Warn,
+                    "off" => BuildCheck::Off,
+                    _ => return Err(invalid_value()),
+                };
+                Ok(())
+            },
+            "validate_update" => parse_bool(value).map(|value| self.validate_update = value),
+            "validate_game" => parse_bool(value).map(|value| self.validate_game = value),
+            "validation_threads" => match value.parse::<usize>() {
+                Ok(threads) if threads > 0 => {
+                    self.validation_threads = threads;
+                    Ok(())
+                },
+                _ => Err(invalid_value()),
+            },
+            "create_backup" => parse_bool(value).map(|value| self.create_backup = value),
+            "backup_format" => {
+                self.backup_format = match value.to_lowercase().as_str() {
+                    "folder" => BackupFormat::Folder,
+                    "zip" => BackupFormat::Zip,
+                    "tar.zst" => BackupFormat::TarZst,
+                    "store" => BackupFormat::Store,
+                    _ => return Err(invalid_value()),
+                };
+                Ok(())
+            },
+            "dry_run" => parse_bool(value).map(|value| self.dry_run = value),
+            "copy_files" => parse_bool(value).map(|value| self.copy_files = value),
+            "remove_files" => parse_bool(value).map(|value| self.remove_files = value),
+            _ => Err(Error::UnknownField(field.to_string())),
+        }
+    }
+
+    fn confirm(&self, prompt: &str) -> Result<()> {
+        if self.assume_yes {
+            return Ok(());
+        }
+        if output::is_json() {
+            output::message("Add \"--yes\" to confirm when using \"--output json\".");
+            return Err(Error::Cancelled);
+        }
+        let input = get_input(prompt);
+        match input.to_lowercase().as_str() {
+            "y" | "yes" => Ok(()),
+            _ => Err(Error::Cancelled),
+        }
+    }
+
+    fn changes(&self) -> Result<Changes> {
+        Changes::parse_changes(self.changes_file.as_ref().ok_or(Error::MissingPath("a changes file"))?)
+    }
+
+    /// Returns the manifest file, or else the hashes of the changed files from the changes file.
+    fn manifest(&self) -> Result<Manifest> {
+        match &self.manifest_file {
+            Some(file) => Manifest::parse_manifest(file),
+            None => self.changes().ok()
+                .and_then(|changes| changes.final_manifest())
+                .ok_or(Error::MissingPath("a manifest file or a changes file with hashes (see the documentation)")),
+        }
+    }
+
+    fn has_manifest(&self) -> bool {
+        self.manifest_file.is_some() || self.changes().is_ok_and(|changes| changes.final_manifest().is_some())
+    }
+
+    pub fn update_game(&mut self) -> Result<()> {
+        let game_directory = self.game_directory.clone().ok_or(Error::MissingPath("a game directory"))?;
+        if self.update_directory.is_none() && self.update_archive.is_none() {
+            return Err(Error::MissingPath("an update directory or archive"));
+        };
+        if Journal::exists(&game_directory) {
+            return Err(Error::InterruptedUpdate(game_directory));
+        }
+
+        let changes = self.changes()?;
+
+        output::message(&format!("Updating {} with files in {} from {}.\n",
+                                 display_name(&game_directory),
+                                 display_name(self.update_archive.as_ref().or(self.update_directory.as_ref()).unwrap()),
+                                 display_name(self.changes_file.as_ref().unwrap())));
+        output::message(&self.to_string());
+
+        if self.check_initial_build != BuildCheck::Off {
+            self.check_build(&game_directory, &changes)?;
+        }
+
+        let backup = self.create_backup.then(|| Backup::new(&game_directory, &changes, self.changes_file.as_deref())).transpose()?;
+        let options = UpdateOptions {
+            update_directory: self.update_directory.clone(),
+            update_archive: self.update_archive.clone(),
+            backup_directory: backup.as_ref().map(|backup| backup.directory(&game_directory)),
+            copy_files: self.copy_files,
+            remove_files: self.remove_files,
+        };
+        let plan = update::plan_update(&game_directory, &changes, &options);
+        if self.dry_run {
+            return update::show_plan(&game_directory, &plan);
+        }
+
+        self.confirm("Continue? [y/N]: ")?;
+
+        // Files in archives are validated while they are copied.
+        let mut manifest = None;
+        if self.validate_update && self.has_manifest() {
+            manifest = Some(self.manifest()?);
+            if self.update_archive.is_none() {
+                let validation = manifest.as_ref().unwrap().validate_files(self.update_directory.as_ref().unwrap(), Some(&changes), self.validation_threads);
+                if let Err(error) = validation {
+                    // Without anyone to ask, a broken update is only applied if explicitly allowed.
+                    if self.assume_yes && !self.ignore_validation {
+                        return Err(error);
+                    }
+                    eprintln!("Error validating update files: {}", error);
+                    self.confirm("Continue? [y/N]: ")?;
+                }
+            }
+        }
+
+        if let Some(backup) = &backup {
+            backup::create_backup(&game_directory, backup, &changes)?;
+        }
+        let result = update::apply_update(&game_directory, plan, manifest.as_ref().filter(|_| self.update_archive.is_some()));
+        self.finish_update(result, self.game_manifest(), backup.as_ref().map(|backup| backup.id.as_str()))
+    }
+
+    /// Applies several updates in one go, ordered from the oldest to the newest build, with a
+    /// single backup of the game files and a single validation at the end. Each path is a changes
+    /// file, an update folder or archive, or a folder of updates.
+    pub fn update_chain(&mut self, paths: &[PathBuf]) -> Result<()> {
+        let packages = chain::find_chain(paths)?;
+        self.apply_chain(packages)
+    }
+
+    /// Detects the installed build (unless given) and updates the game to the target build with
+    /// the fewest updates from the library folder.
+    pub fn update_to(&mut self, library: &Path, final_build: &str, initial_build: Option<&str>) -> Result<()> {
+        let game_directory = self.game_directory.clone().ok_or(Error::MissingPath("a game directory"))?;
+        let packages = chain::find_packages(library)?;
+        let initial_build = match initial_build {
+            Some(build) => build.to_string(),
+            None => {
+                output::message(&format!("Detecting the build of {}.", game_directory.display()));
+                let builds = detect::matching_builds(&game_directory, &chain::fingerprints(&packages))?;
+                match builds.as_slice() {
+                    [build] => build.clone(),
+                    builds => return Err(Error::UnknownBuild(builds.to_vec())),
+                }
+            },
+        };
+        if initial_build == final_build {
+            output::message(&format!("The game is already at build {}.", final_build));
+            return Ok(());
+        }
+
+        let packages = chain::plan_path(&packages, &initial_build, final_build)?;
+        output::message(&format!("Found {} updates from build {} to build {}.", packages.len(), initial_build, final_build));
+        self.apply_chain(packages)
+    }
+
+    fn apply_chain(&mut self, packages: Vec<Package>) -> Result<()> {
+        let game_directory = self.game_directory.clone().ok_or(Error::MissingPath("a game directory"))?;
+        if Journal::exists(&game_directory) {
+            return Err(Error::InterruptedUpdate(game_directory));
+        }
+
+        let updates: Vec<Changes> = packages.iter().map(|package| package.changes.clone()).collect();
+        let changes = Changes::combine(&updates);
+
+        output::message(&format!("Updating {} from build {} to build {} with {} updates:",
+                                 display_name(&game_directory),
+                                 changes.initial_build, changes.final_build, packages.len()));
+        for package in &packages {
+            output::message(&format!("  {} to {} ({})", package.changes.initial_build, package.changes.final_build,
+                                     package.location().display()));
+        }
+        output::message(&format!("\n{}", self));
+
+        if self.check_initial_build != BuildCheck::Off {
+            self.check_build(&game_directory, &changes)?;
+        }
+
+        // The backup holds the files from before the whole chain, so it keeps the combined changes.
+        let backup = self.create_backup.then(|| Backup::new(&game_directory, &changes, None)).transpose()?;
+        let backup_directory = backup.as_ref().map(|backup| backup.directory(&game_directory));
+        let mut plans = packages.iter().map(|package| update::plan_update(&game_directory, &package.changes, &UpdateOptions {
+            update_directory: package.update_directory.clone(),
+            update_archive: package.update_archive.clone(),
+            backup_directory: backup_directory.clone(),
+            copy_files: self.copy_files,
+            remove_files: self.remove_files,
+        }));
+        let first = plans.next().unwrap();
+        let plan = update::chain_plans(first, plans.collect());
+        if self.dry_run {
+            return update::show_plan(&game_directory, &plan);
+        }
+
+        self.confirm("Continue? [y/N]: ")?;
+
+        if self.validate_update {
+            for package in &packages {
+                let Some(manifest) = package.changes.final_manifest() else {
+                    continue;
+                };
+                let validation = match (&package.update_archive, &package.update_directory) {
+                    (Some(archive), _) => manifest.validate_archive(archive, Some(&package.changes)),
+                    (None, Some(update_directory)) => manifest.validate_files(update_directory, Some(&package.changes), self.validation_threads),
+                    _ => Ok(()),
+                };
+                if let Err(error) = validation {
+                    if self.assume_yes && !self.ignore_validation {
+                        return Err(error);
+                    }
+                    eprintln!("Error validating update files of {}: {}", package.location().display(), error);
+                    self.confirm("Continue? [y/N]: ")?;
+                }
+            }
+        }
+
+        if let Some(backup) = &backup {
+            backup::create_backup(&game_directory, backup, &changes)?;
+        }
+        let result = update::apply_update(&game_directory, plan, None);
+        self.finish_update(result, Ok(changes.final_manifest().filter(|_| self.validate_game)),
+                           backup.as_ref().map(|backup| backup.id.as_str()))
+    }
+
+    /// Checks the game against the initial build of the update, refusing to continue if files
+    /// conflict, or asking first when only warning.
+    fn check_build(&self, game_directory: &Path, changes: &Changes) -> Result<()> {
+        let initial_manifest = match &self.initial_manifest_file {
+            Some(file) => Some(Manifest::parse_manifest(file)?),
+            None => None,
+        };
+        match update::check_initial_build(game_directory, changes, initial_manifest.as_ref(), self.validation_threads) {
+            Err(Error::MissingPath(source)) => {
+                // Older changes files have no hashes, which should not stop them from being installed.
+                eprintln!("Warning: Cannot check the initial build without {}.", source);
+                Ok(())
+            },
+            Err(error @ Error::WrongBuild { .. }) if self.check_initial_build == BuildCheck::Warn => {
+                eprintln!("Warning: {}", error);
+                self.confirm("Continue anyway? [y/N]: ")
+            },
+            result => result,
+        }
+    }
+
+    /// Returns the manifest to validate the game files against after updating, if enabled.
+    fn game_manifest(&self) -> Result<Option<Manifest>> {
+        if self.validate_game && self.has_manifest() {
+            self.manifest().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Records the files of the backup in the backup index, storing it in the backup format if the update finished,
+    /// and validates the game files after an update against the manifest, keeping the error of the
+    /// update. The manifest is only read after the backup is recorded, so the backup is kept even
+    /// if the manifest cannot be read.
+    fn finish_update(&self, result: Result<()>, manifest: Result<Option<Manifest>>, backup: Option<&str>) -> Result<()> {
+        let mut errors: Vec<Error> = result.err().into_iter().collect();
+        if let Some(id) = backup {
+            let game_directory = self.game_directory.as_ref().unwrap();
+            // Interrupted updates are rolled back from the folder, so it is kept until the update finishes.
+            let format = if errors.is_empty() { self.backup_format } else { BackupFormat::Folder };
+            let location = backup::index_backup(game_directory, id)
+                .and_then(|indexed| indexed.then(|| backup::store_backup(game_directory, id, format)).transpose());
+            match location {
+                Ok(Some(location)) => output::message(&format!("Backed up the files to {}.", location.display())),
+                Ok(None) => {},
+                Err(error) => errors.push(error),
+            }
+        }
+        match manifest {
+            Ok(Some(manifest)) => errors.extend(manifest.validate_files(self.game_directory.as_ref().unwrap(), None, self.validation_threads).err()),
+            Ok(None) => {},
+            Err(error) => errors.push(error),
+        }
+        output::message("Finished updating.");
+        Error::collect(errors)
+    }
+
+    /// Finishes an update that was interrupted, using the journal in the game directory.
+    pub fn resume_update(&mut self) -> Result<()> {
+        let game_directory = self.game_directory.clone().ok_or(Error::MissingPath("a game directory"))?;
+        let journal = update::open_journal(&game_directory)?;
+        output::message(&format!("Resuming update of {} ({} of {} operations remaining).",
+                                 game_directory.display(), journal.remaining(), journal.plan.operations.len()));
+        self.confirm("Continue? [y/N]: ")?;
+
+        let backup = journal.plan.backup_directory.as_ref()
+            .and_then(|directory| directory.file_name()?.to_str().map(str::to_string));
+        // The manifest only describes a single update, not a chain.
+        if !journal.plan.chain.is_empty() {
+            let result = update::apply_journal(&game_directory, journal, None);
+            return self.finish_update(result, Ok(None), backup.as_deref());
+        }
+        let mut manifest = None;
+        if self.validate_update && self.has_manifest() && journal.plan.update_archive.is_some() {
+            manifest = Some(self.manifest()?);
+        }
+        let result = update::apply_journal(&game_directory, journal, manifest.as_ref());
+        self.finish_update(result, self.game_manifest(), backup.as_deref())
+    }
+
+    /// Offers to resume or roll back an interrupted update found in the game directory.
+    pub fn check_journal(&mut self) {
+        if !self.game_directory.as_ref().is_some_and(|game_directory| Journal::exists(game_directory)) {
+            return;
+        }
+        println!("\nAn interrupted update was found in {}.", self.game_directory.as_ref().unwrap().display());
+        let input = get_input("Resume, roll back or ignore it? [resume/rollback/ignore]:");
+        let result = match input.to_lowercase().as_str() {
+            "resume" => self.resume_update(),
+            "rollback" => self.rollback_game(None),
+            _ => {
+                println!("Enter \"resume\" or \"rollback\" to deal with it later.");
+                Ok(())
+            },
+        };
+        if let Err(error) = result {
+            eprintln!("Error: {}", error);
+        }
+    }
+
+    /// Rolls back the interrupted update if there is one, or else the update of the given backup
+    /// (by default the latest one). Backups made before the backup index are restored with the
+    /// changes file. A restored backup is removed, so the next rollback reverts the update before.
+    pub fn rollback_game(&mut self, id: Option<&str>) -> Result<()> {
+        let game_directory = self.game_directory.clone().ok_or(Error::MissingPath("a game directory"))?;
+        if Journal::exists(&game_directory) {
+            let journal = update::open_journal(&game_directory)?;
+            output::message(&format!("Rolling back the interrupted update of {}.", game_directory.display()));
+            self.confirm("Continue? [y/N]: ")?;
+            let backup = journal.plan.backup_directory.as_ref()
+                .and_then(|directory| directory.file_name()?.to_str().map(str::to_string));
+            update::rollback_journal(&game_directory, journal)?;
+            return match backup {
+                Some(id) if BackupIndex::load(&game_directory)?.find(&id).is_some() => backup::remove_backup(&game_directory, &id),
+                _ => Ok(()),
+            };
+        }
+
+        let index = BackupIndex::load(&game_directory)?;
+        let backup = select_backup(&game_directory, &index, id)?;
+        // Later updates may have changed the files again, so an older backup can only be restored
+        // if the game is still at the build it was made for.
+        if let Some(backup) = backup.filter(|backup| index.latest().is_some_and(|latest| latest.id != backup.id)) {
+            let changes = backup.changes(&game_directory)?.reverse();
+            update::check_initial_build(&game_directory, &changes, None, self.validation_threads)?;
+        }
+        match backup {
+            Some(backup) => output::message(&format!("Rolling back {} from build {} to build {} with the backup {}.\n",
+                                                     display_name(&game_directory),
+                                                     backup.final_build, backup.initial_build, backup.id)),
+            None => {
+                let backup_directory = game_directory.join(BACKUP_DIRECTORY);
+                if !backup::has_legacy_backup(&game_directory, &index)? {
+                    return Err(Error::InvalidPath { path: backup_directory, expected: "a folder with backups" });
+                }
+                self.changes()?;
+                output::message(&format!("Rolling back {} with files in {} from {}.\n",
+                                         display_name(&game_directory),
+                                         display_name(&backup_directory),
+                                         display_name(self.changes_file.as_ref().unwrap())));
+            },
+        }
+
+        self.confirm("Continue? [y/N]: ")?;
+        let result = self.restore_game(&game_directory, backup, true);
+        output::message("Finished rolling back.");
+        result
+    }
+
+    /// Rolls the game back to an earlier build by restoring the backups of the updates since then,
+    /// from the newest to the oldest.
+    pub fn rollback_to(&mut self, build: &str) -> Result<()> {
+        let game_directory = self.game_directory.clone().ok_or(Error::MissingPath("a game directory"))?;
+        if Journal::exists(&game_directory) {
+            return Err(Error::InterruptedUpdate(game_directory));
+        }
+        let index = BackupIndex::load(&game_directory)?;
+        let latest = index.latest().ok_or(Error::InvalidPath {
+            path: game_directory.join(BACKUP_DIRECTORY),
+            expected: "a folder with backups",
+        })?;
+
+        // Find the installed build from what each backup knows about the build it was made for.
+        let mut fingerprints: Vec<Fingerprint> = vec![];
+        for backup in &index.backups {
+            let fingerprint = backup.changes(&game_directory)?.fingerprints().1;
+            if !fingerprint.files.is_empty() {
+                fingerprints.push(fingerprint);
+            }
+        }
+        let mut current_builds = if fingerprints.is_empty() {
+            // Without hashes, the game can only be assumed to be at the build of the latest update.
+            vec![latest.final_build.clone()]
+        } else {
+            detect::matching_builds(&game_directory, &fingerprints)?
+        };
+        current_builds.sort();
+        current_builds.dedup();
+        if current_builds.is_empty() {
+            return Err(Error::InvalidPath { path: game_directory, expected: "at a build the backups were made for" });
+        }
+        if current_builds.iter().any(|current| current == build) {
+            output::message(&format!("{} is already at build {}.", display_name(&game_directory), build));
+            return Ok(());
+        }
+
+        // Follow the backups from the installed build back to the build, preferring the latest
+        // backup when there are several for a build.
+        let mut steps: Vec<&Backup> = vec![];
+        for current_build in &current_builds {
+            steps.clear();
+            let mut current = current_build.as_str();
+            while current != build {
+                let Some(backup) = index.backups.iter()
+                    .filter(|backup| backup.final_build == current && !steps.iter().any(|step| step.id == backup.id))
+                    .max_by_key(|backup| backup.created) else {
+                    break;
+                };
+                steps.push(backup);
+                current = &backup.initial_build;
+            }
+            if current == build {
+                break;
+            }
+        }
+        if steps.last().is_none_or(|last| last.initial_build != build) {
+            return Err(Error::NoUpdatePath {
+                initial_build: current_builds.join(" or "),
+                final_build: build.to_string(),
+            });
+        }
+
+        output::message(&format!("Rolling back {} from build {} to build {} with {} backups:",
+                                 display_name(&game_directory),
+                                 steps[0].final_build, build, steps.len()));
+        for backup in &steps {
+            output::message(&format!("  {} to {} ({})", backup.final_build, backup.initial_build, backup.id));
+        }
+        self.confirm("Continue? [y/N]: ")?;
+        for backup in steps {
+            // The manifest describes the newest build, not the builds in between.
+            self.restore_game(&game_directory, Some(backup), false)?;
+        }
+        output::message("Finished rolling back.");
+        Ok(())
+    }
+
+    /// Restores the files of the backup, or of the backup folder with the changes file for backups
+    /// made before the backup index, and validates them. The backup is removed once restored.
+    fn restore_game(&self, game_directory: &Path, backup: Option<&Backup>, validate_manifest: bool) -> Result<()> {
+        let (changes, result) = match backup {
+            Some(backup) => {
+                let changes = backup.changes(game_directory)?;
+                let result = match backup.format {
+                    BackupFormat::Zip | BackupFormat::TarZst => {
+                        update::restore_backup_archive(game_directory, &backup.location(game_directory), &changes)
+                    },
+                    _ => update::restore_backup(game_directory, &changes, |path| backup.backup_file(game_directory, path)),
+                };
+                (changes, result)
+            },
+            None => {
+                let changes = self.changes()?;
+                let backup_directory = game_directory.join(BACKUP_DIRECTORY);
+                let result = update::restore_backup(game_directory, &changes, |path| Some(backup_directory.join(path)));
+                (changes, result)
+            },
+        };
+        // Nothing is left to validate if the backup was incomplete or a file could not be restored.
+        result?;
+        let mut errors: Vec<Error> = vec![];
+        if validate_manifest && self.validate_game && self.manifest_file.is_some() {
+            let manifest = self.manifest()?;
+            errors.extend(manifest.validate_unchanged_files(game_directory, &changes, self.validation_threads).err());
+        }
+        // The restored files should match the initial build again.
+        if self.validate_game && changes.files.values().any(|info| info.old_sha1.is_some()) {
+            errors.extend(update::check_initial_build(game_directory, &changes, None, self.validation_threads).err());
+        }
+        if let (Some(backup), true) = (backup, errors.is_empty()) {
+            errors.extend(backup::remove_backup(game_directory, &backup.id).err());
+        }
+        Error::collect(errors)
+    }
+
+    /// Writes an update that reverts the update of the backup (by default the latest one) to the
+    /// output directory, so other games at the same build can be downgraded.
+    pub fn create_reverse(&self, output_directory: &Path, id: Option<&str>) -> Result<()> {
+        let game_directory = self.game_directory.clone().ok_or(Error::MissingPath("a game directory"))?;
+        let index = BackupIndex::load(&game_directory)?;
+        let backup = select_backup(&game_directory, &index, id)?
+            .ok_or(Error::InvalidPath { path: game_directory.join(BACKUP_DIRECTORY), expected: "a folder with backups" })?;
+        creator::create_reverse(&game_directory, backup, output_directory).map(|_| ())
+    }
+
+    /// Handles the "backup" command: lists, shows, verifies or prunes the backups of the game.
+    pub fn manage_backups(&self, arguments: &[&str]) -> Result<()> {
+        let game_directory = self.game_directory.clone().ok_or(Error::MissingPath("a game directory"))?;
+        let index = BackupIndex::load(&game_directory)?;
+        let find = |id: Option<&&str>| {
+            let id = id.ok_or(Error::MissingPath("the id of a backup (see \"backup list\")"))?;
+            index.find(id).ok_or_else(|| Error::InvalidPath {
+                path: game_directory.join(BACKUP_DIRECTORY).join(id),
+                expected: "a backup in the backup index",
+            })
+        };
+        match arguments.first() {
+            None | Some(&"list") => {
+                if index.backups.is_empty() {
+                    output::message(&format!("There are no backups in {}.", game_directory.join(BACKUP_DIRECTORY).display()));
+                    return Ok(());
+                }
+                for backup in &index.backups {
+                    let usage = backup::disk_usage(&game_directory, &index, backup)?;
+                    output::message(&format!("{}\n  Build {} to {}, made {}, {} files, {} on disk ({}).",
+                                             backup.id, backup.initial_build, backup.final_build,
+                                             backup::format_date(backup.created), backup.files.len(), format_bytes(usage),
+                                             backup.format));
+                }
+                output::message(&format!("{} backups using {} in {}.", index.backups.len(), format_bytes(backup::total_disk_usage(&game_directory)?),
+                                         game_directory.join(BACKUP_DIRECTORY).display()));
+                Ok(())
+            },
+            Some(&"show") => {
+                let backup = find(arguments.get(1))?;
+                let spacing = 20;
+                output::message(&format!("{:spacing$} {}", "Backup:", backup.id));
+                output::message(&format!("{:spacing$} {} ({})", "Location:", backup.location(&game_directory).display(), backup.format));
+                output::message(&format!("{:spacing$} {} ({})", "App:", backup.app, backup.depot));
+                output::message(&format!("{:spacing$} {} to {}", "Builds:", backup.initial_build, backup.final_build));
+                output::message(&format!("{:spacing$} {}", "Made:", backup::format_date(backup.created)));
+                output::message(&format!("{:spacing$} {}", "Changes file:", match &backup.changes_file {
+                    Some(file) => file.display().to_string(),
+                    None => "None (a chain of updates)".to_string(),
+                }));
+                output::message(&format!("{:spacing$} {} ({} on disk)", "Size:", format_bytes(backup.size()),
+                                         format_bytes(backup::disk_usage(&game_directory, &index, backup)?)));
+                output::message(&format!("\nFiles ({}):", backup.files.len()));
+                for file in &backup.files {
+                    output::message(&format!("  {} ({}, {})", file.path, format_bytes(file.size), file.sha1));
+                }
+                Ok(())
+            },
+            Some(&"verify") => backup::verify_backup(&game_directory, find(arguments.get(1))?),
+            Some(&"prune") => {
+                let value = |option: &str| arguments.iter().position(|argument| *argument == option)
+                    .map(|position| {
+                        let value = arguments.get(position + 1).copied().unwrap_or_default();
+                        value.parse::<u64>().map_err(|_| Error::InvalidValue { field: option.to_string(), value: value.to_string() })
+                    })
+                    .transpose();
+                let keep = value("--keep")?.map(|keep| keep as usize);
+                let older_than = value("--older-than")?.map(|days| days * 86400);
+                if keep.is_none() && older_than.is_none() {
+                    return Err(Error::Usage("Enter the backups to keep with --keep <number> or --older-than <days>.".to_string()));
+                }
+                // The backup of an interrupted update is needed to roll it back.
+                if Journal::exists(&game_directory) {
+                    return Err(Error::InterruptedUpdate(game_directory));
+                }
+
+                let pruned = backup::backups_to_prune(&index, keep, older_than);
+                if pruned.is_empty() {
+                    output::message("There are no backups to prune.");
+                    return Ok(());
+                }
+                output::message(&format!("Removing {} backups:", pruned.len()));
+                for backup in &pruned {
+                    output::message(&format!("  {} ({})", backup.id, format_bytes(backup::disk_usage(&game_directory, &index, backup)?)));
+                }
+                self.confirm("Continue? [y/N]: ")?;
+                // Backups in the store can share files, so the space freed is measured.
+                let before = backup::total_disk_usage(&game_directory)?;
+                for backup in &pruned {
+                    backup::remove_backup(&game_directory, &backup.id)?;
+                }
+                let freed = before.saturating_sub(backup::total_disk_usage(&game_directory)?);
+                output::message(&format!("Removed {} backups, freeing {}.", pruned.len(), format_bytes(freed)));
+                Ok(())
+            },
+            Some(command) => Err(Error::Usage(format!("Unknown command \"backup {}\". Enter \"backup list\", \"show\", \"verify\" or \"prune\".", command))),
+        }
+    }
+
+    pub fn show_changes(&self) -> Result<()> {
+        let changes = self.changes()?;
+
+        let spacing = 20;
+        output::message(&format!("Changes for {} ({}):", changes.name, changes.app));
+        output::message(&format!("{:spacing$} {}+", "Initial Build:", changes.initial_build));
+        output::message(&format!("{:spacing$} {}", "Final Build:", changes.final_build));
+        output::message(&format!("{:spacing$} {}", "Depot:", changes.depot));
+        output::message(&format!("{:spacing$} {}", "Manifest:", changes.manifest));
+        let display_vec = |vec: &Vec<String>| {
+            vec.iter().map(|value| format!("  {}", value)).collect::<Vec<String>>().join("\n")
+        };
+
+        if !changes.added.is_empty() {
+            output::message(&format!("Added:\n{}", display_vec(&changes.added)));
+        }
+        if !changes.removed.is_empty() {
+            output::message(&format!("Removed:\n{}", display_vec(&changes.removed)));
+        }
+        if !changes.modified.is_empty() {
+            output::message(&format!("Modified:\n{}", display_vec(&changes.modified)));
+        }
+        Ok(())
+    }
+
+    /// Compares the game directory with the manifests (or folders of manifests) to find the
+    /// installed build, and checks whether the changes file applies to it. Without manifests, the
+    /// manifest files in the settings are used.
+    pub fn status(&self, manifests: &[PathBuf]) -> Result<()> {
+        let game_directory = self.game_directory.as_ref().ok_or(Error::MissingPath("a game directory"))?;
+        let mut files = vec![];
+        let manifests = match manifests {
+            [] => self.manifest_file.iter().chain(&self.initial_manifest_file).cloned().collect(),
+            manifests => manifests.to_vec(),
+        };
+        for path in manifests {
+            if !path.is_dir() {
+                files.push(path);
+                continue;
+            }
+            let mut entries: Vec<PathBuf> = read_dir(&path).map_err(Error::io(&path))?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|entry| entry.extension().is_some_and(|extension| extension == "txt" || extension == "sha1"))
+                .collect();
+            entries.sort();
+            files.extend(entries);
+        }
+        let changes = self.changes().ok();
+        if files.is_empty() && changes.is_none() {
+            return Err(Error::MissingPath("manifests or a changes file to compare the game with"));
+        }
+
+        if !files.is_empty() {
+            let mut fingerprints = vec![];
+            for file in &files {
+                let manifest = Manifest::parse_manifest(file)?;
+                fingerprints.push(Fingerprint::from_manifest(&display_name(file), &manifest));
+            }
+            output::message(&format!("Comparing {} with {} manifests.", game_directory.display(), files.len()));
+            let comparisons = detect::compare_builds(game_directory, &fingerprints)?;
+            for comparison in &comparisons {
+                output::message(&format!("  {}: {} of {} files match, {} differ, {} missing.", comparison.build, comparison.matched,
+                                         comparison.matched + comparison.mismatched.len() + comparison.missing.len(),
+                                         comparison.mismatched.len(), comparison.missing.len()));
+            }
+            let best = comparisons.iter()
+                .min_by_key(|comparison| (comparison.differences(), std::cmp::Reverse(comparison.matched)))
+                .unwrap();
+            if best.differences() == 0 {
+                output::message(&format!("The game matches {}.", best.build));
+            } else {
+                output::message(&format!("The closest match is {} ({} files differ).", best.build, best.differences()));
+            }
+        }
+
+        if let Some(changes) = changes {
+            let update = format!("The changes file (build {} to {})", changes.initial_build, changes.final_build);
+            let (initial, last) = changes.fingerprints();
+            if initial.is_empty() {
+                output::message(&format!("{} has no hashes to check the game against.", update));
+                return Ok(());
+            }
+            let last_empty = last.is_empty();
+            let comparisons = detect::compare_builds(game_directory, &[initial, last])?;
+            if comparisons[0].differences() == 0 {
+                output::message(&format!("{} applies to the game.", update));
+            } else if !last_empty && comparisons[1].differences() == 0 {
+                output::message(&format!("{} has already been applied.", update));
+            } else {
+                output::message(&format!("{} does not apply: {} files differ from build {}.",
+                                         update, comparisons[0].differences(), changes.initial_build));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self, directory: &str) -> Result<()> {
+        let directory = directory.trim();
+        if directory.is_empty() {
+            return Err(Error::MissingPath("the directory you want to validate (\"update\" or \"game\")"));
+        }
+
+        let manifest = self.manifest()?;
+
+        if directory == "update" {
+            let changes = self.changes().ok();
+            if let Some(update_archive) = &self.update_archive {
+                return manifest.validate_archive(update_archive, changes.as_ref());
+            }
+            let update_directory = self.update_directory.as_ref().ok_or(Error::MissingPath("an update directory"))?;
+            manifest.validate_files(update_directory, changes.as_ref(), self.validation_threads)
+        } else if directory == "game" {
+            let game_directory = self.game_directory.as_ref().ok_or(Error::MissingPath("a game directory"))?;
+            manifest.validate_files(game_directory, None, self.validation_threads)
+        } else {
+            Err(Error::InvalidValue { field: "validate".to_string(), value: directory.to_string() })
+        }
+    }
+}
+
+/// Location of the per-user settings file, following the conventions of each platform.
+fn user_settings_file() -> Option<PathBuf> {
+    let directory = if cfg!(windows) {
+        var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        var_os("HOME").map(|home| PathBuf::from(home).join("Library").join("Application Support"))
+    } else {
+        var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+            .or_else(|| var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    directory.map(|directory| directory.join("RedAlt-SteamUp-Installer").join("settings.json"))
+}
+
+/// Returns the backup with the id, or the latest backup if no id is given.
+fn select_backup<'a>(game_directory: &Path, index: &'a BackupIndex, id: Option<&str>) -> Result<Option<&'a Backup>> {
+    match id {
+        Some(id) => index.find(id).map(Some).ok_or_else(|| Error::InvalidPath {
+            path: game_directory.join(BACKUP_DIRECTORY).join(id),
+            expected: "a backup in the backup index",
+        }),
+        None => Ok(index.latest()),
+    }
+}